@@ -1,22 +1,195 @@
 "use strict";
 
-const { listenerNew, listenerStart, listenerStop } = require("./index.node");
+const {
+  listenerNew,
+  listenerStart,
+  listenerStartPort,
+  listenerStartConfig,
+  listenerStartMany,
+  listenerTrackWindow,
+  listenerTrackWindowByTitle,
+  listenerStop,
+  listenerReconfigure,
+  listenerGetHistory,
+  listenerGetLast,
+  listenerGetUsageStats,
+  listenerResetUsageStats,
+  listenerSetIgnoredHwnds,
+  listenerStartDiagnosticSession,
+  activeHookCount,
+  droppedEventCount,
+  stopAll,
+  shutdownRuntime,
+  getCapabilities,
+  listWindows,
+} = require("./index.node");
+
+// Mark a start promise as handled, so callers that don't await it don't get
+// an unhandled rejection when the hooks fail. Awaiting it still rejects.
+function ready(promise) {
+  promise.catch(() => {});
+  return promise;
+}
+
+// Call `cb` with the event only. Anything that isn't a function is passed
+// through as is, for the native side to reject with a `TypeError`.
+function eventCallback(cb) {
+  return typeof cb === "function" ? (event) => cb(event) : cb;
+}
 
 class WindowForegroundListener {
   constructor() {
     this.listener = listenerNew();
   }
 
-  start(pid, cb) {
-    listenerStart.call(this.listener, pid, (name) => {
-      cb(name);
-      return "";
-    });
+  // `start`, `startConfig`, `startPort`, `trackWindow` and
+  // `trackWindowByTitle` return a promise that resolves once the hooks are
+  // installed, or rejects with an error whose `code` is "ERR_HOOK_FAILED".
+  // The callback is never called before it has resolved: events that happen
+  // in between are held back and delivered afterwards, in order. Ignoring the
+  // promise is fine; a failure still calls `onClose("hookFailed")`.
+  //
+  // `options.onClose(reason)` is called once when the listener ends, with
+  // "stopped", "maxEvents", "hookFailed", "runtimeShutdown" or "panicked".
+  // `options.onError({ code, message, errno, retriable })` is called for
+  // failures on the listener's side, like hooks that still couldn't be
+  // installed after retrying or an NDJSON write failing.
+  // With `options.payloadFormat: "msgpack"`, `cb` receives each event as a
+  // `Buffer` of MessagePack instead of an object, encoded off the main thread.
+  // With `options.delta: true`, each event only has the fields that changed
+  // since the previous one, plus a `seq` counter. The first event is complete
+  // and has `full: true`, as is the next one after an event fails to reach
+  // `cb`; merge each event onto the last to get the current state. Fields
+  // that disappear are sent as `null`.
+  // With `options.strictOrder: true`, events reach `cb` in exactly the order
+  // the OS reported them, each with a `seq` that only ever increases. Events
+  // wait in a buffer of `options.strictOrderBufferSize` (default 1024) while
+  // `cb` catches up; once it is full, new ones are dropped, counted in
+  // `droppedEventCount()` and in the next event's `droppedBefore`. It can't
+  // be combined with `throttleMs`, `settleMs`, the debounce options or
+  // `delta`.
+  // With `options.rawHandles: true`, every event has `pid` and `rawHwnd`, the
+  // window handle as a BigInt.
+  start(pid, cb, options) {
+    // Returning `false` or `{ stop: true }` from `cb` stops the listener.
+    return ready(listenerStart.call(this.listener, pid, eventCallback(cb), options));
+  }
+
+  // Like `start`, with everything in one object:
+  // `{ pid, callback, ...options }`. Unknown keys are warned about.
+  startConfig(config) {
+    return ready(listenerStartConfig.call(this.listener, config));
+  }
+
+  // Posts each event to `port` with `postMessage` instead of calling back.
+  // Events are posted from the calling thread, so call this from a worker to
+  // keep event delivery off the main thread.
+  startPort(pid, port, options) {
+    return ready(listenerStartPort.call(this.listener, pid, port, options));
+  }
+
+  // Runs one subscription per entry of `configs`, each an options object
+  // with an optional `pid`. Events from all of them go to `cb`, tagged with
+  // the entry's index as `listenerId`. Returns a promise that resolves with
+  // the ids once every entry's hooks are installed, before `cb` first runs.
+  // If any entry's hooks fail, it rejects and the other entries stop.
+  startMany(configs, cb) {
+    return ready(listenerStartMany.call(this.listener, eventCallback(cb), configs));
+  }
+
+  // Follows one window until it is destroyed, with `eventType` one of
+  // "foreground", "foregroundLost", "locationChange", "nameChange" or
+  // "destroy".
+  trackWindow(hwnd, cb, options) {
+    return ready(listenerTrackWindow.call(this.listener, hwnd, eventCallback(cb), options));
+  }
+
+  // Like `trackWindow`, for the window currently titled exactly `title`.
+  // Throws if there is none. If several match, the topmost is tracked, or
+  // with `options.multipleMatches: "error"` it throws instead. The window
+  // stays tracked if it's renamed.
+  trackWindowByTitle(title, cb, options) {
+    return ready(
+      listenerTrackWindowByTitle.call(this.listener, title, eventCallback(cb), options)
+    );
+  }
+
+  // Replaces the options of the running listener without reinstalling its
+  // hooks. Throws, leaving the current options in place, if they're invalid.
+  reconfigure(options) {
+    listenerReconfigure.call(this.listener, options);
   }
 
   stop() {
     listenerStop.call(this.listener);
   }
+
+  // Replaces the `ignoreHwnds` set of the running listener.
+  setIgnoredHwnds(hwnds) {
+    listenerSetIgnoredHwnds.call(this.listener, hwnds);
+  }
+
+  // Recent foreground events, newest first. Requires the `historySize` option.
+  getHistory() {
+    return listenerGetHistory.call(this.listener);
+  }
+
+  // Foreground milliseconds per PID or executable path since the listener
+  // started or was last reset. Requires the `usageStatsBy` option.
+  //
+  // With `{ detailed: true }` each key maps to `{ foregroundMs, lifetimeMs,
+  // lifetimeShare }` instead, where for PID keys `lifetimeMs` is how long the
+  // process has been running and `lifetimeShare` the fraction of the time it
+  // has been observed running that it was foreground. Both are null for
+  // executable keys. When a PID is reused by a new process, the earlier
+  // process's entry moves to `pid-processStartTime`, as in `stableId`.
+  getUsageStats(options) {
+    return listenerGetUsageStats.call(this.listener, options);
+  }
+
+  resetUsageStats() {
+    listenerResetUsageStats.call(this.listener);
+  }
+
+  // Record every hook event, delivery and error for `durationMs`, then
+  // resolve with a report to attach to a bug report: `{ startedAt,
+  // durationMs, rawEvents, deliveredEvents, droppedEvents, latencyUs,
+  // entries, truncatedEntries }`, where `entries` lists what happened (up to
+  // 1000) and `latencyUs` summarizes processing latency as `{ min, max, mean,
+  // p95 }`. With `options.log: true`, each hook event and delivery is also
+  // logged to stderr as it happens. The listener behaves exactly as it
+  // otherwise would.
+  startDiagnosticSession(durationMs, options) {
+    return listenerStartDiagnosticSession.call(this.listener, durationMs, options);
+  }
+
+  // The last event delivered to `cb`, or `null` if there hasn't been one
+  // since the listener was started.
+  getLast() {
+    return listenerGetLast.call(this.listener);
+  }
 }
 
 module.exports = WindowForegroundListener;
+module.exports.activeHookCount = activeHookCount;
+// `stopAll(timeoutMs = 5000)` stops every listener of every instance and
+// resolves with whether they all unhooked within the timeout.
+module.exports.stopAll = stopAll;
+// `shutdownRuntime(timeoutMs = 5000)` stops every listener, waits for their
+// hooks to be removed, then shuts down the native runtime, all within the
+// timeout. Resolves with whether every listener stopped cleanly. Starting a
+// listener afterwards creates a new runtime.
+module.exports.shutdownRuntime = shutdownRuntime;
+// Events that couldn't be delivered because the main thread's queue was full,
+// after any `callbackRetries` (doubling from `callbackRetryMs`, default 10),
+// or because a `strictOrder` buffer was.
+module.exports.droppedEventCount = droppedEventCount;
+// `interactiveDesktop` is `false` in a service (session 0) or on a
+// non-interactive window station. Starting a listener there throws an error
+// whose `code` is "ERR_NO_INTERACTIVE_DESKTOP". `features` says which Cargo
+// features the addon was built with: `uia` and `process-info`, both on by
+// default. Options that need a missing one throw.
+module.exports.getCapabilities = getCapabilities;
+// `listWindows({ monitorIndex })`: visible top-level windows, front to back,
+// optionally only those centered on one monitor.
+module.exports.listWindows = listWindows;
@@ -0,0 +1,79 @@
+use std::{mem, time::Duration};
+
+use serde::Serialize;
+use winapi::{
+    shared::windef::HWND,
+    um::winuser::{
+        GetAsyncKeyState, GetGUIThreadInfo, GUITHREADINFO, VK_LBUTTON, VK_LWIN, VK_MBUTTON,
+        VK_MENU, VK_RBUTTON, VK_RWIN, VK_XBUTTON1, VK_XBUTTON2,
+    },
+};
+
+use crate::{idle, window};
+
+// What most likely made a window the foreground, for `includeCause`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Cause {
+    Keyboard,
+    Mouse,
+    Programmatic,
+    Unknown,
+}
+
+// Input older than this when the change is handled didn't cause it.
+const INPUT_WINDOW: Duration = Duration::from_millis(500);
+
+// Guess the cause of a foreground change from the input state when it is
+// handled, which is a few milliseconds after it happened:
+//
+// - No input at all for `INPUT_WINDOW`: programmatic.
+// - A mouse button still down, or the new window's thread capturing the
+//   mouse: mouse.
+// - Alt or a Windows key still down, as during Alt-Tab or Win+number:
+//   keyboard.
+// - Otherwise unknown.
+//
+// The further the event is delayed, the less this is worth: a quick click or
+// Alt-Tab is usually released by then, and comes out as unknown. Input from
+// another app (an automation tool, a remote desktop client) looks like the
+// user's, and a program activating itself right after unrelated input looks
+// like that input caused it. Nothing here can tell Alt-Tab from Win+Tab or a
+// taskbar click from a click in the window.
+pub fn classify(window: HWND) -> Cause {
+    match idle::get_idle_time() {
+        Ok(idle) if idle >= INPUT_WINDOW => return Cause::Programmatic,
+        Ok(_) => (),
+        Err(_) => return Cause::Unknown,
+    }
+
+    let mouse_down = [VK_LBUTTON, VK_RBUTTON, VK_MBUTTON, VK_XBUTTON1, VK_XBUTTON2]
+        .iter()
+        .any(|&key| is_down(key));
+    if mouse_down || has_capture(window) {
+        return Cause::Mouse;
+    }
+
+    if [VK_MENU, VK_LWIN, VK_RWIN].iter().any(|&key| is_down(key)) {
+        return Cause::Keyboard;
+    }
+
+    Cause::Unknown
+}
+
+fn is_down(key: i32) -> bool {
+    (unsafe { GetAsyncKeyState(key) } as u16) & 0x8000 != 0
+}
+
+// Whether a window of the window's thread has captured the mouse, which it
+// does while a button that went down on it is held.
+fn has_capture(window: HWND) -> bool {
+    let thread_id = match window::get_window_thread_id(window) {
+        Ok(thread_id) => thread_id,
+        Err(_) => return false,
+    };
+
+    let mut info: GUITHREADINFO = unsafe { mem::zeroed() };
+    info.cbSize = mem::size_of::<GUITHREADINFO>() as u32;
+    unsafe { GetGUIThreadInfo(thread_id, &mut info) != 0 && !info.hwndCapture.is_null() }
+}
@@ -0,0 +1,91 @@
+use std::ptr;
+
+use winapi::{
+    shared::winerror::SUCCEEDED,
+    um::{
+        combaseapi::{CoInitializeEx, CoUninitialize},
+        objbase::COINIT_MULTITHREADED,
+        unknwnbase::IUnknown,
+    },
+    Interface,
+};
+#[cfg(feature = "uia")]
+use winapi::{
+    shared::wtypes::BSTR,
+    um::oleauto::{SysFreeString, SysStringLen},
+};
+
+// Initializes COM on the current thread for as long as it is alive.
+//
+// Listener tasks run on arbitrary tokio worker threads, so COM is initialized
+// around each use rather than once per thread.
+pub struct ComGuard(bool);
+
+impl ComGuard {
+    pub fn new() -> Self {
+        let hr = unsafe { CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED) };
+        // RPC_E_CHANGED_MODE means COM is already initialized differently on
+        // this thread; it's still usable but must not be uninitialized by us.
+        Self(SUCCEEDED(hr))
+    }
+}
+
+impl Drop for ComGuard {
+    fn drop(&mut self) {
+        if self.0 {
+            unsafe { CoUninitialize() };
+        }
+    }
+}
+
+// An owned COM interface pointer, released when dropped.
+pub struct ComPtr<T: Interface>(*mut T);
+
+impl<T: Interface> ComPtr<T> {
+    // Takes ownership of one reference. `ptr` must be a valid, non-null
+    // interface pointer.
+    pub unsafe fn from_raw(ptr: *mut T) -> Self {
+        Self(ptr)
+    }
+
+    pub fn as_ref(&self) -> &T {
+        unsafe { &*self.0 }
+    }
+
+    // For passing to calls that take an interface pointer. Ownership stays
+    // here.
+    #[cfg(feature = "uia")]
+    pub fn as_raw(&self) -> *mut T {
+        self.0
+    }
+
+    pub fn cast<U: Interface>(&self) -> Option<ComPtr<U>> {
+        let mut out = ptr::null_mut();
+        let unknown = self.0 as *mut IUnknown;
+        let hr = unsafe { (*unknown).QueryInterface(&U::uuidof(), &mut out) };
+        if SUCCEEDED(hr) && !out.is_null() {
+            Some(ComPtr(out as *mut U))
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Interface> Drop for ComPtr<T> {
+    fn drop(&mut self) {
+        unsafe { (*(self.0 as *mut IUnknown)).Release() };
+    }
+}
+
+// Take ownership of a `BSTR` returned by a COM call and convert it.
+#[cfg(feature = "uia")]
+pub unsafe fn take_bstr(bstr: BSTR) -> Option<String> {
+    if bstr.is_null() {
+        return None;
+    }
+
+    let len = SysStringLen(bstr) as usize;
+    let text = String::from_utf16_lossy(std::slice::from_raw_parts(bstr, len));
+    SysFreeString(bstr);
+    Some(text)
+}
@@ -0,0 +1,71 @@
+use neon::prelude::*;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::value;
+
+// `delta` mode: each event carries only the fields that changed since the
+// previously delivered one, plus `seq`. The first event, and the first after
+// one that didn't reach the callback, is a full snapshot marked `full: true`,
+// so a consumer that merges deltas onto it always ends up with the current
+// event. A field that is present in one event and absent from the next is
+// sent as `null`.
+#[derive(Default)]
+pub struct DeltaEncoder {
+    previous: Option<Map<String, Value>>,
+    // Counts every encoded event, so a consumer can tell one went missing.
+    seq: u64,
+}
+
+impl DeltaEncoder {
+    pub fn encode<T: Serialize>(&mut self, payload: &T) -> serde_json::Result<Value> {
+        let current = match serde_json::to_value(payload)? {
+            Value::Object(current) => current,
+            other => return Ok(other),
+        };
+
+        let mut delta = match &self.previous {
+            Some(previous) => {
+                let mut delta: Map<_, _> = current
+                    .iter()
+                    .filter(|(key, value)| previous.get(*key) != Some(value))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                for key in previous.keys() {
+                    if !current.contains_key(key) {
+                        delta.insert(key.clone(), Value::Null);
+                    }
+                }
+                delta
+            }
+            None => {
+                let mut full = current.clone();
+                full.insert("full".into(), Value::Bool(true));
+                full
+            }
+        };
+        delta.insert("seq".into(), self.seq.into());
+
+        self.seq += 1;
+        self.previous = Some(current);
+
+        Ok(Value::Object(delta))
+    }
+
+    // Send a full snapshot next, after an event that may not have arrived.
+    pub fn resync(&mut self) {
+        self.previous = None;
+    }
+}
+
+// Convert an encoded delta for the callback, with `rawHwnd`, when it changed,
+// as a BigInt like in full events.
+pub fn to_js<'a, C: Context<'a>>(cx: &mut C, delta: &Value) -> JsResult<'a, JsValue> {
+    let value = value::to_js(cx, delta)?;
+    if let Some(raw_hwnd) = delta.get("rawHwnd").and_then(Value::as_i64) {
+        let obj = value.downcast_or_throw::<JsObject, _>(cx)?;
+        value::set_bigint(cx, obj, "rawHwnd", raw_hwnd)?;
+    }
+
+    Ok(value)
+}
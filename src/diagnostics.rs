@@ -0,0 +1,184 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::error::ErrorReport;
+use crate::event::EventType;
+
+// Recorded entries are capped so a long session on a busy desktop can't grow
+// without bound. Counts and latencies keep covering everything.
+const MAX_ENTRIES: usize = 1000;
+
+// A `startDiagnosticSession`: while one is running the listener task records
+// what it sees here, until the session's timer takes it out of the state and
+// turns it into a report. Nothing about the listener's behavior changes.
+pub struct DiagnosticSession {
+    // Also log each hook event and delivery to stderr, with `{ log: true }`.
+    // Off by default so a session doesn't flood the host's output.
+    log: bool,
+    started: Instant,
+    started_at: SystemTime,
+    // `droppedEventCount()` when the session started.
+    dropped_at_start: usize,
+    raw_events: u64,
+    delivered_events: u64,
+    // Processing latencies of delivered events, in microseconds.
+    latencies: Vec<u64>,
+    entries: Vec<Entry>,
+    truncated_entries: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+enum Entry {
+    // A hook event as received, before any filtering.
+    Raw {
+        elapsed_ms: u64,
+        event: u32,
+        hwnd: String,
+        object_id: i32,
+    },
+    // An event handed to the callback, with how it went.
+    Delivered {
+        elapsed_ms: u64,
+        #[serde(rename = "type")]
+        event_type: EventType,
+        hwnd: String,
+        latency_us: u64,
+        error: Option<String>,
+    },
+    Error {
+        elapsed_ms: u64,
+        #[serde(flatten)]
+        error: ErrorReport,
+    },
+}
+
+// What `startDiagnosticSession` resolves with.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticReport {
+    // Milliseconds since the Unix epoch.
+    started_at: u64,
+    duration_ms: u64,
+    raw_events: u64,
+    delivered_events: u64,
+    // Events that couldn't be queued to the main thread, by any listener.
+    dropped_events: usize,
+    latency_us: Option<LatencySummary>,
+    entries: Vec<Entry>,
+    truncated_entries: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LatencySummary {
+    min: u64,
+    max: u64,
+    mean: u64,
+    p95: u64,
+}
+
+impl DiagnosticSession {
+    pub fn new(dropped_at_start: usize, log: bool) -> Self {
+        Self {
+            log,
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            dropped_at_start,
+            raw_events: 0,
+            delivered_events: 0,
+            latencies: Vec::new(),
+            entries: Vec::new(),
+            truncated_entries: 0,
+        }
+    }
+
+    pub fn record_raw(&mut self, event: u32, hwnd: String, object_id: i32) {
+        if self.log {
+            eprintln!("[diagnostics] event {:#06x} hwnd {} object {}", event, hwnd, object_id);
+        }
+        self.raw_events += 1;
+        let elapsed_ms = self.elapsed_ms();
+        self.push(Entry::Raw {
+            elapsed_ms,
+            event,
+            hwnd,
+            object_id,
+        });
+    }
+
+    pub fn record_delivered(
+        &mut self,
+        event_type: EventType,
+        hwnd: String,
+        latency_us: u64,
+        error: Option<String>,
+    ) {
+        if self.log {
+            eprintln!(
+                "[diagnostics] delivered {:?} hwnd {} in {}us{}",
+                event_type,
+                hwnd,
+                latency_us,
+                error.as_ref().map_or(String::new(), |err| format!(": {}", err))
+            );
+        }
+        self.delivered_events += 1;
+        self.latencies.push(latency_us);
+        let elapsed_ms = self.elapsed_ms();
+        self.push(Entry::Delivered {
+            elapsed_ms,
+            event_type,
+            hwnd,
+            latency_us,
+            error,
+        });
+    }
+
+    pub fn record_error(&mut self, error: ErrorReport) {
+        let elapsed_ms = self.elapsed_ms();
+        self.push(Entry::Error { elapsed_ms, error });
+    }
+
+    pub fn into_report(mut self, dropped_now: usize) -> DiagnosticReport {
+        self.latencies.sort_unstable();
+        let latency_us = if self.latencies.is_empty() {
+            None
+        } else {
+            let count = self.latencies.len();
+            Some(LatencySummary {
+                min: self.latencies[0],
+                max: self.latencies[count - 1],
+                mean: self.latencies.iter().sum::<u64>() / count as u64,
+                p95: self.latencies[(count * 95 / 100).min(count - 1)],
+            })
+        };
+
+        DiagnosticReport {
+            started_at: self
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            duration_ms: self.elapsed_ms(),
+            raw_events: self.raw_events,
+            delivered_events: self.delivered_events,
+            dropped_events: dropped_now.saturating_sub(self.dropped_at_start),
+            latency_us,
+            entries: self.entries,
+            truncated_entries: self.truncated_entries,
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    fn push(&mut self, entry: Entry) {
+        if self.entries.len() < MAX_ENTRIES {
+            self.entries.push(entry);
+        } else {
+            self.truncated_entries += 1;
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::{fmt, io, path::PathBuf};
+
+use neon::prelude::*;
+use serde::Serialize;
+
+// Errors from the listener machinery. The per-field getters in `window` and
+// `process` keep returning `io::Result`, since a failed read there just leaves
+// a field `null`; their errors convert into `Win32` where one needs to be
+// reported.
+#[derive(Debug)]
+pub enum Error {
+    // `SetWinEventHook` failed.
+    HookFailed(io::Error),
+    // Removing a hook failed, leaving it installed.
+    UnhookFailed(io::Error),
+    // A Win32 call failed with this `GetLastError` code.
+    Win32(u32),
+    Io(io::Error),
+    // An event didn't reach the JavaScript callback.
+    Callback(CallError),
+    // The global tokio runtime couldn't be created.
+    RuntimeInit(io::Error),
+    // Running where there's no foreground to follow, see
+    // `session::has_interactive_desktop`.
+    NoInteractiveDesktop,
+    NdjsonOpen(PathBuf, io::Error),
+    NdjsonWrite(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// ERROR_NOT_ENOUGH_MEMORY, ERROR_OUTOFMEMORY, ERROR_NO_SYSTEM_RESOURCES,
+// ERROR_TIMEOUT and ERROR_NOT_ENOUGH_QUOTA.
+const TRANSIENT_ERRORS: &[u32] = &[8, 14, 1450, 1460, 1816];
+
+// An error as reported to `onError`, with the same fields as a thrown one.
+#[derive(Serialize)]
+pub struct ErrorReport {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errno: Option<u32>,
+    retriable: bool,
+}
+
+impl Error {
+    // The `code` property of the JS error the error is thrown as.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::HookFailed(_) => "ERR_HOOK_FAILED",
+            Error::UnhookFailed(_) => "ERR_UNHOOK_FAILED",
+            Error::Win32(_) => "ERR_WIN32",
+            Error::Io(_) => "ERR_IO",
+            Error::Callback(_) => "ERR_CALLBACK_FAILED",
+            Error::RuntimeInit(_) => "ERR_RUNTIME_INIT",
+            Error::NoInteractiveDesktop => "ERR_NO_INTERACTIVE_DESKTOP",
+            Error::NdjsonOpen(..) | Error::NdjsonWrite(_) => "ERR_NDJSON",
+        }
+    }
+
+    // Whether trying again might succeed. Only hook failures are retried:
+    // running out of memory, handles or threads is usually temporary, while
+    // e.g. access being denied by security software is not.
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            Error::HookFailed(err) => match err.raw_os_error() {
+                Some(code) => TRANSIENT_ERRORS.contains(&(code as u32)),
+                // Not from the OS: the hook thread couldn't be spawned or
+                // stopped answering.
+                None => true,
+            },
+            _ => false,
+        }
+    }
+
+    // Throw as an `Error` with `code` and `retriable` set, and `errno` for
+    // Win32 failures.
+    pub fn throw<'a, C: Context<'a>, T>(&self, cx: &mut C) -> NeonResult<T> {
+        let err = cx.error(self.to_string())?;
+        let code = cx.string(self.code());
+        err.set(cx, "code", code)?;
+        if let Some(errno) = self.os_error() {
+            let errno = cx.number(errno);
+            err.set(cx, "errno", errno)?;
+        }
+        let retriable = cx.boolean(self.is_retriable());
+        err.set(cx, "retriable", retriable)?;
+
+        cx.throw(err)
+    }
+
+    // The argument `onError` is called with.
+    pub fn report(&self) -> ErrorReport {
+        ErrorReport {
+            code: self.code(),
+            message: self.to_string(),
+            errno: self.os_error(),
+            retriable: self.is_retriable(),
+        }
+    }
+
+    fn os_error(&self) -> Option<u32> {
+        match self {
+            Error::Win32(code) => Some(*code),
+            Error::HookFailed(err)
+            | Error::UnhookFailed(err)
+            | Error::Io(err)
+            | Error::RuntimeInit(err)
+            | Error::NdjsonOpen(_, err)
+            | Error::NdjsonWrite(err) => err.raw_os_error().map(|code| code as u32),
+            Error::Callback(_) | Error::NoInteractiveDesktop => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::HookFailed(err) => write!(f, "Failed to install hook: {}", err),
+            Error::UnhookFailed(err) => write!(f, "Failed to remove hook: {}", err),
+            Error::Win32(code) => write!(f, "{}", io::Error::from_raw_os_error(*code as i32)),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Callback(err) => write!(f, "Failed to call JavaScript: {}", err),
+            Error::RuntimeInit(err) => write!(f, "Failed to create the tokio runtime: {}", err),
+            Error::NoInteractiveDesktop => write!(
+                f,
+                "No interactive desktop: foreground events aren't available in a service or on a non-interactive window station"
+            ),
+            Error::NdjsonOpen(path, err) => write!(
+                f,
+                "Failed to open `ndjsonPath` {}: {}",
+                path.display(),
+                err
+            ),
+            Error::NdjsonWrite(err) => write!(f, "Failed to write NDJSON: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::HookFailed(err)
+            | Error::UnhookFailed(err)
+            | Error::Io(err)
+            | Error::RuntimeInit(err)
+            | Error::NdjsonOpen(_, err)
+            | Error::NdjsonWrite(err) => Some(err),
+            Error::Win32(_) | Error::Callback(_) | Error::NoInteractiveDesktop => None,
+        }
+    }
+}
+
+// OS errors keep their code; anything else stays an `Io` error.
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(code) => Error::Win32(code as u32),
+            None => Error::Io(err),
+        }
+    }
+}
+
+impl From<CallError> for Error {
+    fn from(err: CallError) -> Self {
+        Error::Callback(err)
+    }
+}
+
+// Why an event didn't reach its callback.
+#[derive(Debug)]
+pub enum CallError {
+    // The main thread's queue was full, or the environment was shutting down.
+    NotQueued,
+    // The callback ran but threw, or returned a control value that isn't
+    // understood.
+    Threw,
+    // The callback didn't return within `callbackTimeoutMs`.
+    TimedOut,
+    // The event couldn't be encoded for `payloadFormat`.
+    Encode(String),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallError::NotQueued => write!(f, "the event couldn't be queued to the main thread"),
+            CallError::Threw => write!(f, "the callback threw"),
+            CallError::TimedOut => write!(f, "the callback timed out"),
+            CallError::Encode(err) => write!(f, "the event couldn't be encoded: {}", err),
+        }
+    }
+}
@@ -0,0 +1,975 @@
+use neon::prelude::*;
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+#[cfg(any(feature = "uia", feature = "process-info"))]
+use std::time::Instant;
+use std::time::{Duration, UNIX_EPOCH};
+use winapi::shared::windef::{HWND, RECT};
+use winapi::um::winnt::SYNCHRONIZE;
+use winapi::um::winuser::{SW_SHOWMAXIMIZED, SW_SHOWMINIMIZED};
+
+use crate::cause::Cause;
+use crate::monitor::Monitors;
+use crate::options::StartOptions;
+use crate::process::ProcessHandle;
+use crate::session::SessionChange;
+use crate::shell::{self, PinnedItems};
+#[cfg(feature = "uia")]
+use crate::uia;
+use crate::{handle, process, value, window};
+
+// The payload delivered to the JavaScript callback for each foreground change.
+//
+// It is built on the listener task and converted to a JS object on the main
+// thread, so it holds plain values rather than handles. Optional field groups
+// are `None` when not requested and are omitted from the JS object; a
+// requested field that couldn't be read is `Some(None)` and becomes `null`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForegroundEvent {
+    pub event_type: EventType,
+    // With `settleMs`: `false` for a foreground change as it happens, `true`
+    // for the repeat once the foreground has stayed put.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settled: Option<bool>,
+    // Which config of `listenerStartMany` produced the event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listener_id: Option<u32>,
+    // Absent for events that aren't about a window, such as `idle`.
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_hwnd"
+    )]
+    pub hwnd: Option<isize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stable_id: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_ms: Option<u64>,
+    // The kind of `sessionChange` event.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_change: Option<SessionChange>,
+    // Whether the window still existed when the event was handed to the
+    // callback.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_alive: Option<bool>,
+    // The `awayFromPid`/`awayFromExe` process the foreground just left.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left_pid: Option<u32>,
+    // With `strictOrder`, the WinEvent's place in arrival order, see
+    // `ordered::Sequence`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dropped_before: Option<u64>,
+    // A guess at what caused the foreground change, see `cause::classify`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cause: Option<Cause>,
+    // Skipped in minimal mode.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub geometry: Option<Geometry>,
+    // `null` for the first foreground change. Skipped in minimal mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub same_process_as_previous: Option<Option<bool>>,
+    // Whether the clipboard changed since the previous foreground change, in
+    // `includeClipboardChange` mode. Only the clipboard's sequence number is
+    // read, never its contents, so this is a coarse signal: copying the same
+    // thing again also counts. `null` for the first foreground change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clipboard_changed: Option<Option<bool>>,
+    // Why the previous foreground window lost the foreground, in
+    // `includePreviousExitReason` mode, see `ExitReason::of`. `null` for the
+    // first foreground change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_exit_reason: Option<Option<ExitReason>>,
+    // The previous and new foreground windows, in `includeTransition` mode.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<Transition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<Option<String>>,
+    // The title as read, with `sanitizeTitle`, if sanitizing changed it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_title: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub title_parts: Option<TitleParts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_pinned: Option<Option<bool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtual_desktop_id: Option<Option<String>>,
+    // The active tab's address, for supported browsers. See
+    // `EventBuilder::browser_url`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub browser_url: Option<Option<String>>,
+    // The window, then its owners or parents up to the root.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ancestry: Option<Vec<Ancestor>>,
+    // Best-effort, see `window::is_modal`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_modal: Option<Option<bool>>,
+    // Best-effort position of the window's taskbar button, see
+    // `uia::taskbar_button_names`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taskbar_index: Option<Option<usize>>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub process_times: Option<ProcessTimes>,
+    // The owning process's working set and CPU time, see
+    // `EventBuilder::resource_usage`.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub resource_usage: Option<ResourceUsage>,
+    // The UI Automation name of the focused element, falling back to the
+    // window title.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accessible_name: Option<Option<String>>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub composition: Option<Composition>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub placement: Option<Placement>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exe_path: Option<Option<String>>,
+    // The path as reported by Windows, present when `exePath` was
+    // canonicalized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_exe_path: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_line: Option<Option<String>>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub parent_process: Option<ParentProcess>,
+    // Which filters this event passed, in `debugFilters` mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filters: Option<BTreeMap<&'static str, bool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_event: Option<RawEvent>,
+    // Microseconds from receiving the WinEvent to having built the payload,
+    // in `debugLatency` mode. Excludes throttling and the callback.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub processing_latency_us: Option<u64>,
+    // Present in `rawHandles` mode regardless of other field options.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub raw_handles: Option<RawHandles>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EventType {
+    Foreground,
+    // A foreground change the window made itself, with no recent user input.
+    // Replaces `foreground` with `focusStolenThresholdMs`.
+    FocusStolen,
+    Idle,
+    Active,
+    // With `sessionEvents`.
+    SessionChange,
+    // With `fullscreenEvents`.
+    FullscreenEnter,
+    FullscreenExit,
+    // With `windowListIntervalMs`.
+    WindowOpened,
+    WindowClosed,
+    // The following are only emitted for a tracked window.
+    ForegroundLost,
+    LocationChange,
+    NameChange,
+    Destroy,
+}
+
+impl EventType {
+    // Whether the foreground window changed, however it happened.
+    pub fn is_foreground_change(self) -> bool {
+        matches!(self, EventType::Foreground | EventType::FocusStolen)
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Geometry {
+    pub rect: Option<Rect>,
+    pub is_topmost: Option<bool>,
+    // Index into the stable monitor order, see `monitor::enumerate_monitors`.
+    pub monitor_index: Option<usize>,
+    pub monitor_rect: Option<Rect>,
+    // The monitor's bounds minus the taskbar and other app bars.
+    pub monitor_work_area: Option<Rect>,
+    pub is_primary_monitor: Option<bool>,
+}
+
+// Both ends of a foreground change, so an overlay can animate from one window
+// to the other. `from` is `null` for the first event.
+#[derive(Clone, Serialize)]
+pub struct Transition {
+    pub from: Option<WindowAnchor>,
+    pub to: WindowAnchor,
+}
+
+// A window and its rect when it was last delivered as the foreground.
+#[derive(Clone, Serialize)]
+pub struct WindowAnchor {
+    #[serde(serialize_with = "serialize_hwnd")]
+    pub hwnd: Option<isize>,
+    pub rect: Option<Rect>,
+}
+
+// One window of the `ancestry` chain.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ancestor {
+    #[serde(serialize_with = "serialize_hwnd")]
+    pub hwnd: Option<isize>,
+    pub class_name: Option<String>,
+    pub title: Option<String>,
+}
+
+// The window followed by its owners or parents up to the root.
+fn ancestry(window: HWND) -> Vec<Ancestor> {
+    window::owner_chain(window)
+        .into_iter()
+        .map(|window| Ancestor {
+            hwnd: Some(handle::from_hwnd(window)),
+            class_name: window::get_class_name(window).ok(),
+            title: window::read_window_text(window).ok().flatten(),
+        })
+        .collect()
+}
+
+// Make a title safe to hand to consumers that choke on control characters:
+//
+// - Tabs, line breaks and other whitespace control characters become a space.
+// - Every other control character (C0, DEL and C1), including embedded NULs,
+//   is removed.
+// - Leading and trailing whitespace is trimmed.
+//
+// Unpaired surrogates are already replaced with U+FFFD when the title is
+// read. The title's length comes from the count `GetWindowTextW` returns, not
+// from the first NUL, so NULs inside that count are part of the title until
+// this removes them.
+pub fn sanitize_title(title: &str) -> String {
+    let clean: String = title
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_control() && c.is_whitespace() => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect();
+
+    clean.trim().to_string()
+}
+
+// A title like `file.txt - Notepad` split around its last separator. Titles
+// without the separator go entirely into `documentTitle`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TitleParts {
+    pub document_title: Option<String>,
+    pub app_title: Option<String>,
+}
+
+impl TitleParts {
+    pub fn split(title: Option<&str>, separator: &str) -> Self {
+        match title.and_then(|title| title.rsplit_once(separator)) {
+            Some((document, app)) => Self {
+                document_title: Some(document.to_string()),
+                app_title: Some(app.to_string()),
+            },
+            None => Self {
+                document_title: title.map(str::to_string),
+                app_title: None,
+            },
+        }
+    }
+}
+
+// The raw identifiers a companion native addon needs to do its own
+// enrichment. `rawHwnd` reaches JS as a BigInt, so it never goes through a
+// lossy JS number; NDJSON and MessagePack carry it as a plain integer.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawHandles {
+    pub raw_hwnd: i64,
+    pub pid: Option<u32>,
+}
+
+// The WinEvent the payload was built from, exactly as the hook received it,
+// in `rawEvent` mode.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawEvent {
+    // The `EVENT_*` constant.
+    pub event: u32,
+    #[serde(serialize_with = "serialize_hwnd")]
+    pub hwnd: Option<isize>,
+    pub id_object: i32,
+    pub id_child: i32,
+    pub id_event_thread: u32,
+    pub dwms_event_time: u32,
+}
+
+// Hints for capture tools choosing a capture strategy.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Composition {
+    pub is_layered: Option<bool>,
+    // Only set for layered windows with a constant alpha.
+    pub opacity: Option<f64>,
+}
+
+// From `GetWindowPlacement`, for snapping and restoring windows.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Placement {
+    // Where the window goes when restored, even while it is maximized or
+    // minimized. In workspace coordinates, which differ from the screen
+    // coordinates of `rect` by the taskbar's size when it is docked at the top
+    // or left of the primary monitor.
+    pub restored_rect: Option<Rect>,
+    pub show_state: Option<ShowState>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ShowState {
+    Normal,
+    Minimized,
+    Maximized,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExitReason {
+    Closed,
+    Minimized,
+    Switched,
+}
+
+impl ExitReason {
+    // Read from the old window's state when the new one is delivered, rather
+    // than from destroy and minimize hooks: a window being closed or
+    // minimized has already been destroyed, hidden or made iconic by the time
+    // the foreground moves on. A window hidden without closing, like an app
+    // going to the tray, also counts as closed.
+    pub fn of(window: HWND) -> Self {
+        if !window::is_window(window) || !window::is_visible(window) {
+            ExitReason::Closed
+        } else if window::is_minimized(window) {
+            ExitReason::Minimized
+        } else {
+            ExitReason::Switched
+        }
+    }
+}
+
+// The process that started the window's process, e.g. the launcher or host
+// app. Both are `null` once the parent has exited.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParentProcess {
+    pub parent_pid: Option<u32>,
+    // The file name of the parent's executable.
+    pub parent_exe: Option<String>,
+}
+
+// The owning process's start time, as a proxy for how long the window has been
+// around, since Windows doesn't record window creation times.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessTimes {
+    // Milliseconds since the Unix epoch.
+    pub process_start_time: Option<u64>,
+    pub process_age_ms: Option<u64>,
+}
+
+// A snapshot of the owning process's resource usage, for annotating app
+// sessions. Both are `null` when the process can't be opened.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUsage {
+    pub working_set_bytes: Option<u64>,
+    // Kernel plus user time since the process started.
+    pub cpu_time_ms: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl From<RECT> for Rect {
+    fn from(rect: RECT) -> Self {
+        Self {
+            left: rect.left,
+            top: rect.top,
+            right: rect.right,
+            bottom: rect.bottom,
+        }
+    }
+}
+
+impl ForegroundEvent {
+    pub fn new(window: HWND) -> Self {
+        Self {
+            hwnd: Some(handle::from_hwnd(window)),
+            is_alive: Some(window::is_window(window)),
+            ..Self::empty(EventType::Foreground)
+        }
+    }
+
+    // An `idle` or `active` event carrying how long the user has been idle.
+    pub fn idle(event_type: EventType, idle: Duration) -> Self {
+        Self {
+            idle_ms: Some(idle.as_millis() as u64),
+            ..Self::empty(event_type)
+        }
+    }
+
+    // A `windowClosed` event. The window is gone, so only its handle is known.
+    pub fn window_closed(hwnd: isize) -> Self {
+        Self {
+            hwnd: Some(hwnd),
+            is_alive: Some(false),
+            ..Self::empty(EventType::WindowClosed)
+        }
+    }
+
+    // A `sessionChange` event, which isn't about any window.
+    pub fn session_change(change: SessionChange) -> Self {
+        Self {
+            session_change: Some(change),
+            ..Self::empty(EventType::SessionChange)
+        }
+    }
+
+    // Every field but the type left out. The constructors above start from
+    // this, so a new field only needs adding here.
+    fn empty(event_type: EventType) -> Self {
+        Self {
+            event_type,
+            settled: None,
+            listener_id: None,
+            hwnd: None,
+            stable_id: None,
+            idle_ms: None,
+            session_change: None,
+            is_alive: None,
+            left_pid: None,
+            seq: None,
+            dropped_before: None,
+            cause: None,
+            geometry: None,
+            same_process_as_previous: None,
+            clipboard_changed: None,
+            previous_exit_reason: None,
+            transition: None,
+            title: None,
+            raw_title: None,
+            title_parts: None,
+            is_pinned: None,
+            is_modal: None,
+            ancestry: None,
+            browser_url: None,
+            virtual_desktop_id: None,
+            taskbar_index: None,
+            process_times: None,
+            resource_usage: None,
+            accessible_name: None,
+            composition: None,
+            placement: None,
+            exe_path: None,
+            raw_exe_path: None,
+            command_line: None,
+            parent_process: None,
+            filters: None,
+            raw_event: None,
+            processing_latency_us: None,
+            raw_handles: None,
+        }
+    }
+
+    pub fn to_js<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsValue> {
+        let value = value::to_js(cx, self)?;
+        if let Some(raw_handles) = &self.raw_handles {
+            let obj = value.downcast_or_throw::<JsObject, _>(cx)?;
+            value::set_bigint(cx, obj, "rawHwnd", raw_handles.raw_hwnd)?;
+        }
+
+        Ok(value)
+    }
+}
+
+// How long a resource usage snapshot is reused for the same process.
+#[cfg(feature = "process-info")]
+const RESOURCE_USAGE_TTL: Duration = Duration::from_secs(1);
+
+// Top-level window classes of Chromium-based browsers and Firefox.
+#[cfg(feature = "uia")]
+const BROWSER_CLASSES: &[&str] = &["Chrome_WidgetWin_1", "MozillaWindowClass"];
+
+// The executables `browserUrl` is read for.
+#[cfg(feature = "uia")]
+const BROWSER_EXES: &[&str] = &[
+    "chrome.exe",
+    "msedge.exe",
+    "brave.exe",
+    "vivaldi.exe",
+    "opera.exe",
+    "firefox.exe",
+];
+
+// How long the taskbar's buttons are reused. Walking them is slow, and they
+// only change when apps open or close windows.
+#[cfg(feature = "uia")]
+const TASKBAR_BUTTONS_TTL: Duration = Duration::from_secs(5);
+
+// Processes whose lookups a `ProcessCache` keeps at once.
+const MAX_CACHED_PROCESSES: usize = 256;
+
+// Per-process lookups, keyed by PID. Each entry holds a handle to its
+// process, so the PID can't be reused while it is cached, and an entry whose
+// process has exited is looked up again. Processes that can't be opened
+// aren't cached. Once full, entries of exited processes make room first, then
+// the least recently used one.
+struct ProcessCache<T> {
+    entries: HashMap<u32, CachedProcess<T>>,
+    // Bumped on every lookup, to find the least recently used entry.
+    clock: u64,
+}
+
+struct CachedProcess<T> {
+    process: ProcessHandle,
+    value: T,
+    used: u64,
+}
+
+impl<T: Clone> ProcessCache<T> {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get_or_insert_with(&mut self, pid: u32, read: impl FnOnce() -> T) -> T {
+        self.get_fresh_or_insert_with(pid, |_| true, read)
+    }
+
+    // Like `get_or_insert_with`, but a cached value `is_fresh` turns down is
+    // read again.
+    fn get_fresh_or_insert_with(
+        &mut self,
+        pid: u32,
+        is_fresh: impl FnOnce(&T) -> bool,
+        read: impl FnOnce() -> T,
+    ) -> T {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&pid) {
+            if !entry.process.has_exited() && is_fresh(&entry.value) {
+                entry.used = self.clock;
+                return entry.value.clone();
+            }
+        }
+
+        // Opened before reading, so a PID reused in between leaves an entry
+        // that is already stale rather than one that never is.
+        let process = match ProcessHandle::open(pid, SYNCHRONIZE) {
+            Ok(process) => process,
+            Err(_) => {
+                self.entries.remove(&pid);
+                return read();
+            }
+        };
+
+        let value = read();
+        if self.entries.len() >= MAX_CACHED_PROCESSES && !self.entries.contains_key(&pid) {
+            self.entries.retain(|_, entry| !entry.process.has_exited());
+        }
+        if self.entries.len() >= MAX_CACHED_PROCESSES && !self.entries.contains_key(&pid) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(&pid, _)| pid);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            pid,
+            CachedProcess {
+                process,
+                value: value.clone(),
+                used: self.clock,
+            },
+        );
+        value
+    }
+}
+
+// Builds foreground events for one listener, holding the lookup caches that
+// the optional fields need between events.
+pub struct EventBuilder {
+    monitors: Option<Monitors>,
+    pinned: Option<PinnedItems>,
+    exe_paths: ProcessCache<Option<PathBuf>>,
+    #[cfg(feature = "process-info")]
+    command_lines: ProcessCache<Option<String>>,
+    #[cfg(feature = "process-info")]
+    parents: ProcessCache<Option<u32>>,
+    #[cfg(feature = "process-info")]
+    resource_usage: ProcessCache<(Instant, ResourceUsage)>,
+    #[cfg(feature = "uia")]
+    taskbar_buttons: Option<(Instant, Vec<String>)>,
+}
+
+impl EventBuilder {
+    pub fn new(options: &StartOptions) -> Self {
+        Self {
+            monitors: None,
+            pinned: options.include_pinned.then(PinnedItems::new),
+            exe_paths: ProcessCache::new(),
+            #[cfg(feature = "process-info")]
+            command_lines: ProcessCache::new(),
+            #[cfg(feature = "process-info")]
+            parents: ProcessCache::new(),
+            #[cfg(feature = "process-info")]
+            resource_usage: ProcessCache::new(),
+            #[cfg(feature = "uia")]
+            taskbar_buttons: None,
+        }
+    }
+
+    // Keep the caches, which don't depend on the options.
+    pub fn reconfigure(&mut self, options: &StartOptions) {
+        if options.include_pinned != self.pinned.is_some() {
+            self.pinned = options.include_pinned.then(PinnedItems::new);
+        }
+    }
+
+    pub fn build(&mut self, window: HWND, options: &StartOptions) -> ForegroundEvent {
+        let mut event = ForegroundEvent::new(window);
+
+        if !options.minimal {
+            let monitor = self.monitors.get_or_insert_with(Monitors::new).lookup(window);
+            event.geometry = Some(Geometry {
+                rect: window::get_window_rect(window).ok().map(Rect::from),
+                is_topmost: window::is_topmost(window).ok(),
+                monitor_index: monitor.as_ref().map(|(index, _)| *index),
+                monitor_rect: monitor.as_ref().map(|(_, info)| info.rect),
+                monitor_work_area: monitor.as_ref().map(|(_, info)| info.work_area),
+                is_primary_monitor: monitor.as_ref().map(|(_, info)| info.is_primary),
+            });
+        }
+
+        if options.include_stable_id {
+            event.stable_id = Some(self.stable_id(window));
+        }
+
+        if options.include_title {
+            let mut title = window::read_window_text(window).ok().flatten();
+            if options.sanitize_title {
+                if let Some(raw) = title.take() {
+                    let clean = sanitize_title(&raw);
+                    if clean != raw {
+                        event.raw_title = Some(raw);
+                    }
+                    title = Some(clean).filter(|clean| !clean.is_empty());
+                }
+            }
+            if let Some(separator) = &options.title_separator {
+                event.title_parts = Some(TitleParts::split(title.as_deref(), separator));
+            }
+            event.title = Some(title);
+        }
+
+        if options.raw_handles {
+            event.raw_handles = Some(RawHandles {
+                raw_hwnd: window as i64,
+                pid: window::get_window_process_id(window).ok(),
+            });
+        }
+
+        if options.include_virtual_desktop {
+            event.virtual_desktop_id = Some(shell::get_window_desktop_id(window));
+        }
+
+        #[cfg(feature = "uia")]
+        if options.include_browser_url {
+            event.browser_url = Some(self.browser_url(window));
+        }
+
+        if options.include_ancestry {
+            event.ancestry = Some(ancestry(window));
+        }
+
+        if options.include_modal {
+            event.is_modal = Some(window::is_modal(window).ok());
+        }
+
+        #[cfg(feature = "uia")]
+        if options.include_taskbar_index {
+            event.taskbar_index = Some(self.taskbar_index(window));
+        }
+
+        if let Some(pinned) = &mut self.pinned {
+            event.is_pinned = Some(pinned.is_pinned(window));
+        }
+
+        #[cfg(feature = "uia")]
+        if options.include_accessible_name {
+            event.accessible_name = Some(
+                uia::focused_element_name()
+                    .or_else(|| window::read_window_text(window).ok().flatten()),
+            );
+        }
+
+        if options.include_composition {
+            let is_layered = window::is_layered(window).ok();
+            event.composition = Some(Composition {
+                is_layered,
+                opacity: match is_layered {
+                    Some(true) => window::get_layered_opacity(window),
+                    _ => None,
+                },
+            });
+        }
+
+        if options.include_restored_rect {
+            let placement = window::get_window_placement(window).ok();
+            event.placement = Some(Placement {
+                restored_rect: placement.map(|placement| Rect::from(placement.rcNormalPosition)),
+                show_state: placement.map(|placement| match placement.showCmd as i32 {
+                    SW_SHOWMINIMIZED => ShowState::Minimized,
+                    SW_SHOWMAXIMIZED => ShowState::Maximized,
+                    _ => ShowState::Normal,
+                }),
+            });
+        }
+
+        if options.include_process_times {
+            let start_time = window::get_window_process_id(window)
+                .ok()
+                .and_then(|pid| process::get_process_start_time(pid).ok());
+            event.process_times = Some(ProcessTimes {
+                process_start_time: start_time
+                    .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                    .map(|time| time.as_millis() as u64),
+                process_age_ms: start_time
+                    .and_then(|time| time.elapsed().ok())
+                    .map(|age| age.as_millis() as u64),
+            });
+        }
+
+        if options.include_exe_path {
+            let exe_path = window::get_window_process_id(window)
+                .ok()
+                .and_then(|pid| self.exe_path(pid));
+            if options.canonicalize_exe_path {
+                event.exe_path = Some(
+                    exe_path
+                        .as_deref()
+                        .map(process::canonicalize_exe_path)
+                        .map(|path| path.to_string_lossy().into_owned()),
+                );
+                event.raw_exe_path =
+                    Some(exe_path.map(|path| path.to_string_lossy().into_owned()));
+            } else {
+                event.exe_path = Some(exe_path.map(|path| path.to_string_lossy().into_owned()));
+            }
+        }
+
+        #[cfg(feature = "process-info")]
+        if options.include_resource_usage {
+            event.resource_usage = Some(
+                window::get_window_process_id(window)
+                    .map(|pid| self.resource_usage(pid))
+                    .unwrap_or(ResourceUsage {
+                        working_set_bytes: None,
+                        cpu_time_ms: None,
+                    }),
+            );
+        }
+
+        #[cfg(feature = "process-info")]
+        if options.include_command_line {
+            event.command_line = Some(
+                window::get_window_process_id(window)
+                    .ok()
+                    .and_then(|pid| self.command_line(pid)),
+            );
+        }
+
+        #[cfg(feature = "process-info")]
+        if options.include_parent_process {
+            let parent_pid = window::get_window_process_id(window)
+                .ok()
+                .and_then(|pid| self.parent_pid(pid));
+            event.parent_process = Some(ParentProcess {
+                parent_pid,
+                parent_exe: parent_pid.and_then(|pid| {
+                    let path = self.exe_path(pid)?;
+                    Some(path.file_name()?.to_string_lossy().into_owned())
+                }),
+            });
+        }
+
+        event
+    }
+
+    // `hwnd-pid-processStartTime`. The OS reuses handles and PIDs, but never
+    // both for a process with the same start time, so a reused handle gets a
+    // new id. Ids are only unique within a boot session, and a handle reused
+    // by a later window of the same process keeps the same id.
+    fn stable_id(&mut self, window: HWND) -> Option<String> {
+        let pid = window::get_window_process_id(window).ok()?;
+        let start_time = process::get_process_start_time(pid).ok()?;
+        let start_time = start_time.duration_since(UNIX_EPOCH).ok()?.as_micros();
+        Some(format!("{}-{}-{}", handle::from_hwnd(window), pid, start_time))
+    }
+
+    // The address bar of a Chrome, Edge, Brave, Vivaldi, Opera or Firefox
+    // window, read through UI Automation within `uia::TIMEOUT`. Both the
+    // window class and the executable are checked, so Electron apps, which
+    // share Chromium's window class, are skipped without a query. Usually the
+    // address without the scheme, exactly as the browser displays it, and
+    // whatever has been typed while the address bar is being edited.
+    #[cfg(feature = "uia")]
+    fn browser_url(&mut self, window: HWND) -> Option<String> {
+        let class_name = window::get_class_name(window).ok()?;
+        if !BROWSER_CLASSES.contains(&class_name.as_str()) {
+            return None;
+        }
+
+        let pid = window::get_window_process_id(window).ok()?;
+        let exe = self.exe_path(pid)?;
+        let exe = exe.file_name()?.to_string_lossy().to_lowercase();
+        if !BROWSER_EXES.contains(&exe.as_str()) {
+            return None;
+        }
+
+        uia::address_bar_value(window)
+    }
+
+    // The first taskbar button whose name contains the window's title. An
+    // ungrouped button is named after its window; a group is named after the
+    // app, so this is often `None` for grouped buttons.
+    #[cfg(feature = "uia")]
+    fn taskbar_index(&mut self, window: HWND) -> Option<usize> {
+        let title = window::read_window_text(window).ok().flatten()?.to_lowercase();
+
+        let stale = self
+            .taskbar_buttons
+            .as_ref()
+            .is_none_or(|(read_at, _)| read_at.elapsed() >= TASKBAR_BUTTONS_TTL);
+        if stale {
+            let names = uia::taskbar_button_names().unwrap_or_default();
+            self.taskbar_buttons = Some((Instant::now(), names));
+        }
+
+        let (_, names) = self.taskbar_buttons.as_ref()?;
+        names
+            .iter()
+            .position(|name| name.to_lowercase().contains(&title))
+    }
+
+    // Cached per process for `RESOURCE_USAGE_TTL`, so switching back and
+    // forth between windows doesn't reread it every time.
+    #[cfg(feature = "process-info")]
+    pub fn resource_usage(&mut self, pid: u32) -> ResourceUsage {
+        let (_, usage) = self.resource_usage.get_fresh_or_insert_with(
+            pid,
+            |(read_at, _)| read_at.elapsed() < RESOURCE_USAGE_TTL,
+            || {
+                let usage = ResourceUsage {
+                    working_set_bytes: process::get_process_working_set(pid).ok(),
+                    cpu_time_ms: process::get_process_cpu_time(pid)
+                        .ok()
+                        .map(|time| time.as_millis() as u64),
+                };
+                (Instant::now(), usage)
+            },
+        );
+        usage
+    }
+
+    // Cached per process. A process can rewrite its command line, but
+    // reading it is too expensive to repeat for every event.
+    #[cfg(feature = "process-info")]
+    pub fn command_line(&mut self, pid: u32) -> Option<String> {
+        self.command_lines
+            .get_or_insert_with(pid, || process::get_process_command_line(pid).ok())
+    }
+
+    // Cached per process, since a process's parent never changes. A parent
+    // that started after the child can't be its parent: the real one exited
+    // and its PID was reused.
+    #[cfg(feature = "process-info")]
+    fn parent_pid(&mut self, pid: u32) -> Option<u32> {
+        self.parents.get_or_insert_with(pid, || {
+            process::get_parent_process_id(pid).ok().filter(|&parent| {
+                match (
+                    process::get_process_start_time(parent),
+                    process::get_process_start_time(pid),
+                ) {
+                    (Ok(parent_start), Ok(start)) => parent_start <= start,
+                    _ => false,
+                }
+            })
+        })
+    }
+
+    // Cached per process, since a process's image never changes.
+    pub fn exe_path(&mut self, pid: u32) -> Option<PathBuf> {
+        self.exe_paths
+            .get_or_insert_with(pid, || process::get_process_image_path(pid).ok())
+    }
+
+    pub fn monitor_index(&mut self, window: HWND) -> Option<usize> {
+        self.monitors
+            .get_or_insert_with(Monitors::new)
+            .index_of(window)
+    }
+}
+
+// HWNDs are delivered as decimal strings, see `handle`.
+fn serialize_hwnd<S: Serializer>(hwnd: &Option<isize>, serializer: S) -> Result<S::Ok, S::Error> {
+    match hwnd {
+        Some(hwnd) => serializer.serialize_str(&handle::to_string(*hwnd)),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn process_cache_reads_a_running_process_once() {
+        let mut cache = ProcessCache::new();
+        let reads = Cell::new(0);
+        let read = || {
+            reads.set(reads.get() + 1);
+            reads.get()
+        };
+
+        let pid = std::process::id();
+        assert_eq!(cache.get_or_insert_with(pid, read), 1);
+        assert_eq!(cache.get_or_insert_with(pid, read), 1);
+        assert_eq!(reads.get(), 1);
+    }
+
+    #[test]
+    fn process_cache_skips_processes_it_cant_open() {
+        let mut cache = ProcessCache::new();
+        let reads = Cell::new(0);
+        let read = || {
+            reads.set(reads.get() + 1);
+            reads.get()
+        };
+
+        // The System Idle Process can't be opened.
+        assert_eq!(cache.get_or_insert_with(0, read), 1);
+        assert_eq!(cache.get_or_insert_with(0, read), 2);
+        assert!(cache.entries.is_empty());
+    }
+}
@@ -0,0 +1,446 @@
+// `filterExpression`: a small boolean expression compiled once at start and
+// evaluated natively for every foreground window, so complex filtering needs
+// no round trip to JavaScript. For example
+//
+//     exe == "chrome.exe" && width > 800
+//     !(className == "ConsoleWindowClass") || title contains "admin"
+//
+// Operators, loosest binding first: `||`, `&&`, `!`, then the comparisons
+// `==`, `!=`, `<`, `<=`, `>`, `>=` and `contains`, none of which chain.
+// Parentheses group. Operands are fields, numbers, double-quoted strings
+// (with `\"` and `\\` escapes), `true`, `false` and `null`.
+//
+// Fields: `hwnd`, `pid`, `exe` (the file name), `exePath`, `title`,
+// `className`, `x`, `y`, `width`, `height` and `monitorIndex`. A field that
+// can't be read is `null`. Fields are only read when reached, so `&&` and `||`
+// short-circuit reads as well as evaluation.
+//
+// String comparisons and `contains` ignore case, since executable names and
+// paths do. `<` and friends compare numbers with numbers and strings with
+// strings, and are false for anything else. Used as a condition, `null`,
+// `false`, `0` and `""` are false and everything else is true.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Hwnd,
+    Pid,
+    Exe,
+    ExePath,
+    Title,
+    ClassName,
+    X,
+    Y,
+    Width,
+    Height,
+    MonitorIndex,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "hwnd" => Field::Hwnd,
+            "pid" => Field::Pid,
+            "exe" => Field::Exe,
+            "exePath" => Field::ExePath,
+            "title" => Field::Title,
+            "className" => Field::ClassName,
+            "x" => Field::X,
+            "y" => Field::Y,
+            "width" => Field::Width,
+            "height" => Field::Height,
+            "monitorIndex" => Field::MonitorIndex,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Number(value) => *value != 0.0,
+            Value::String(value) => !value.is_empty(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Literal(Value),
+    Field(Field),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Compare(CompareOp, Box<Node>, Box<Node>),
+}
+
+// A compiled `filterExpression`.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    root: Node,
+}
+
+// Why an expression didn't compile, with the character offset it was found
+// at.
+#[derive(Debug)]
+pub struct SyntaxError {
+    offset: usize,
+    message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl Expression {
+    pub fn compile(source: &str) -> Result<Self, SyntaxError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let root = parser.parse_or()?;
+        match parser.peek() {
+            Some((offset, token)) => Err(SyntaxError {
+                offset: *offset,
+                message: format!("unexpected {}", token),
+            }),
+            None => Ok(Self { root }),
+        }
+    }
+
+    // Evaluate with `read` supplying the value of each field as it is
+    // reached.
+    pub fn matches(&self, read: &mut impl FnMut(Field) -> Value) -> bool {
+        evaluate(&self.root, read).is_truthy()
+    }
+}
+
+fn evaluate(node: &Node, read: &mut impl FnMut(Field) -> Value) -> Value {
+    match node {
+        Node::Literal(value) => value.clone(),
+        Node::Field(field) => read(*field),
+        Node::Not(node) => Value::Bool(!evaluate(node, read).is_truthy()),
+        Node::And(left, right) => {
+            Value::Bool(evaluate(left, read).is_truthy() && evaluate(right, read).is_truthy())
+        }
+        Node::Or(left, right) => {
+            Value::Bool(evaluate(left, read).is_truthy() || evaluate(right, read).is_truthy())
+        }
+        Node::Compare(op, left, right) => {
+            let left = evaluate(left, read);
+            let right = evaluate(right, read);
+            Value::Bool(compare(*op, &left, &right))
+        }
+    }
+}
+
+fn compare(op: CompareOp, left: &Value, right: &Value) -> bool {
+    use std::cmp::Ordering;
+
+    let ordering = match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.partial_cmp(right),
+        (Value::String(left), Value::String(right)) => {
+            let (left, right) = (left.to_lowercase(), right.to_lowercase());
+            if op == CompareOp::Contains {
+                return left.contains(&right);
+            }
+            Some(left.cmp(&right))
+        }
+        (Value::Bool(left), Value::Bool(right)) if left == right => Some(Ordering::Equal),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None,
+    };
+
+    match op {
+        CompareOp::Eq => ordering == Some(Ordering::Equal),
+        CompareOp::Ne => ordering != Some(Ordering::Equal),
+        CompareOp::Contains => false,
+        _ => match (left, right) {
+            (Value::Number(_), Value::Number(_)) | (Value::String(_), Value::String(_)) => {
+                match (op, ordering) {
+                    (CompareOp::Lt, Some(ordering)) => ordering == Ordering::Less,
+                    (CompareOp::Le, Some(ordering)) => ordering != Ordering::Greater,
+                    (CompareOp::Gt, Some(ordering)) => ordering == Ordering::Greater,
+                    (CompareOp::Ge, Some(ordering)) => ordering != Ordering::Less,
+                    _ => false,
+                }
+            }
+            _ => false,
+        },
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    String(String),
+    And,
+    Or,
+    Not,
+    Compare(CompareOp),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::String(value) => write!(f, "string {:?}", value),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Not => write!(f, "`!`"),
+            Token::Compare(_) => write!(f, "comparison"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, SyntaxError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let next = chars.get(i + 1).copied();
+        let token = match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if next == Some('&') => {
+                i += 1;
+                Token::And
+            }
+            '|' if next == Some('|') => {
+                i += 1;
+                Token::Or
+            }
+            '=' if next == Some('=') => {
+                i += 1;
+                Token::Compare(CompareOp::Eq)
+            }
+            '!' if next == Some('=') => {
+                i += 1;
+                Token::Compare(CompareOp::Ne)
+            }
+            '!' => Token::Not,
+            '<' if next == Some('=') => {
+                i += 1;
+                Token::Compare(CompareOp::Le)
+            }
+            '<' => Token::Compare(CompareOp::Lt),
+            '>' if next == Some('=') => {
+                i += 1;
+                Token::Compare(CompareOp::Ge)
+            }
+            '>' => Token::Compare(CompareOp::Gt),
+            '"' => {
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('"') => break,
+                        Some('\\') => match chars.get(i + 1) {
+                            Some(&c @ ('"' | '\\')) => {
+                                value.push(c);
+                                i += 2;
+                            }
+                            _ => {
+                                return Err(SyntaxError {
+                                    offset: i,
+                                    message: "invalid escape".into(),
+                                })
+                            }
+                        },
+                        Some(&c) => {
+                            value.push(c);
+                            i += 1;
+                        }
+                        None => {
+                            return Err(SyntaxError {
+                                offset: start,
+                                message: "unterminated string".into(),
+                            })
+                        }
+                    }
+                }
+                Token::String(value)
+            }
+            c if c.is_ascii_digit() || (c == '-' && next.is_some_and(|c| c.is_ascii_digit())) => {
+                let mut end = i + 1;
+                while end < chars.len() && (chars[end].is_ascii_digit() || chars[end] == '.') {
+                    end += 1;
+                }
+                let text: String = chars[i..end].iter().collect();
+                let value = text.parse().map_err(|_| SyntaxError {
+                    offset: start,
+                    message: format!("invalid number `{}`", text),
+                })?;
+                i = end - 1;
+                Token::Number(value)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = i + 1;
+                while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                    end += 1;
+                }
+                let name: String = chars[i..end].iter().collect();
+                i = end - 1;
+                if name == "contains" {
+                    Token::Compare(CompareOp::Contains)
+                } else {
+                    Token::Ident(name)
+                }
+            }
+            c => {
+                return Err(SyntaxError {
+                    offset: start,
+                    message: format!("unexpected `{}`", c),
+                })
+            }
+        };
+        tokens.push((start, token));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), SyntaxError> {
+        let token = self.tokens.get(self.position).cloned().ok_or_else(|| SyntaxError {
+            offset: self.tokens.last().map_or(0, |(offset, _)| offset + 1),
+            message: "unexpected end of expression".into(),
+        })?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, expected: &Token) -> bool {
+        if self.peek().map(|(_, token)| token) == Some(expected) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Node, SyntaxError> {
+        let mut node = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            node = Node::Or(Box::new(node), Box::new(self.parse_and()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, SyntaxError> {
+        let mut node = self.parse_not()?;
+        while self.eat(&Token::And) {
+            node = Node::And(Box::new(node), Box::new(self.parse_not()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_not(&mut self) -> Result<Node, SyntaxError> {
+        if self.eat(&Token::Not) {
+            return Ok(Node::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_compare()
+    }
+
+    fn parse_compare(&mut self) -> Result<Node, SyntaxError> {
+        let left = self.parse_operand()?;
+        match self.peek() {
+            Some((_, Token::Compare(op))) => {
+                let op = *op;
+                self.position += 1;
+                let right = self.parse_operand()?;
+                if let Some((offset, Token::Compare(_))) = self.peek() {
+                    return Err(SyntaxError {
+                        offset: *offset,
+                        message: "comparisons don't chain".into(),
+                    });
+                }
+                Ok(Node::Compare(op, Box::new(left), Box::new(right)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Node, SyntaxError> {
+        let (offset, token) = self.next()?;
+        Ok(match token {
+            Token::Open => {
+                let node = self.parse_or()?;
+                if !self.eat(&Token::Close) {
+                    return Err(SyntaxError {
+                        offset,
+                        message: "unclosed `(`".into(),
+                    });
+                }
+                node
+            }
+            Token::Not => Node::Not(Box::new(self.parse_operand()?)),
+            Token::Number(value) => Node::Literal(Value::Number(value)),
+            Token::String(value) => Node::Literal(Value::String(value)),
+            Token::Ident(name) => match name.as_str() {
+                "true" => Node::Literal(Value::Bool(true)),
+                "false" => Node::Literal(Value::Bool(false)),
+                "null" => Node::Literal(Value::Null),
+                _ => match Field::from_name(&name) {
+                    Some(field) => Node::Field(field),
+                    None => {
+                        return Err(SyntaxError {
+                            offset,
+                            message: format!("unknown field `{}`", name),
+                        })
+                    }
+                },
+            },
+            token => {
+                return Err(SyntaxError {
+                    offset,
+                    message: format!("unexpected {}", token),
+                })
+            }
+        })
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use winapi::shared::windef::HWND;
+
+use crate::event::EventBuilder;
+use crate::expr::{Field, Value};
+use crate::options::StartOptions;
+use crate::{handle, window};
+
+// The outcome of each configured filter for one window, keyed by the option
+// name. Unconfigured filters aren't recorded.
+#[derive(Default)]
+pub struct FilterReport {
+    results: BTreeMap<&'static str, bool>,
+    // Keep evaluating after a failure, for `debugFilters`.
+    exhaustive: bool,
+}
+
+impl FilterReport {
+    pub fn new(exhaustive: bool) -> Self {
+        Self {
+            results: BTreeMap::new(),
+            exhaustive,
+        }
+    }
+
+    pub fn record(&mut self, name: &'static str, passed: bool) {
+        self.results.insert(name, passed);
+    }
+
+    pub fn passed(&self) -> bool {
+        self.results.values().all(|&passed| passed)
+    }
+
+    // Whether there's no point evaluating further filters.
+    pub fn is_done(&self) -> bool {
+        !self.exhaustive && !self.passed()
+    }
+
+    pub fn into_results(self) -> BTreeMap<&'static str, bool> {
+        self.results
+    }
+}
+
+// Evaluate every configured filter for a foreground window. Filters are
+// checked before the event is built so dropped events cost as little as
+// possible, and share the builder's caches. `ignored` is whether the window
+// is in the shared ignored set, `None` while that set is empty; it's passed in
+// so the filters don't run under the state lock.
+pub fn evaluate(
+    window: HWND,
+    options: &StartOptions,
+    ignored: Option<bool>,
+    builder: &mut EventBuilder,
+    report: &mut FilterReport,
+) {
+    if let Some(ignored) = ignored {
+        report.record("ignoreHwnds", !ignored);
+        if report.is_done() {
+            return;
+        }
+    }
+
+    if options.app_windows_only {
+        report.record(
+            "appWindowsOnly",
+            window::is_app_window(window).unwrap_or(false),
+        );
+        if report.is_done() {
+            return;
+        }
+    }
+
+    if options.alt_tab_eligible_only {
+        report.record("altTabEligibleOnly", window::is_alt_tab_eligible(window));
+        if report.is_done() {
+            return;
+        }
+    }
+
+    if let Some(monitor_index) = options.monitor_index {
+        report.record(
+            "monitorIndex",
+            builder.monitor_index(window) == Some(monitor_index),
+        );
+        if report.is_done() {
+            return;
+        }
+    }
+
+    if let Some(owner) = options.owner_hwnd {
+        report.record(
+            "ownerHwnd",
+            window::owner_chain(window)
+                .into_iter()
+                .any(|window| handle::from_hwnd(window) == owner),
+        );
+        if report.is_done() {
+            return;
+        }
+    }
+
+    if options.min_width.is_some() || options.min_height.is_some() {
+        // A window whose rect can't be read is let through.
+        let passed = window::get_window_rect(window).map_or(true, |rect| {
+            let width = (rect.right - rect.left).max(0) as usize;
+            let height = (rect.bottom - rect.top).max(0) as usize;
+            options.min_width.is_none_or(|min| width >= min)
+                && options.min_height.is_none_or(|min| height >= min)
+        });
+        report.record("minSize", passed);
+        if report.is_done() {
+            return;
+        }
+    }
+
+    // The title is read here, before the event is built, and read again for
+    // the `title` field, since the window may have been renamed in between.
+    if options.require_title {
+        let title = window::read_window_text(window).ok().flatten();
+        report.record(
+            "requireTitle",
+            title.is_some_and(|title| !title.trim().is_empty()),
+        );
+        if report.is_done() {
+            return;
+        }
+    }
+
+    if let Some(property) = &options.window_property {
+        let passed = match window::get_prop(window, &property.key) {
+            Some(value) => property.value.is_none_or(|expected| expected == value),
+            None => false,
+        };
+        report.record("windowProperty", passed);
+        if report.is_done() {
+            return;
+        }
+    }
+
+    if let Some(expression) = &options.filter_expression {
+        // Read at most once, however many fields need them.
+        let mut pid_cache = None;
+        let mut rect_cache = None;
+        let passed = expression.matches(&mut |field| {
+            let mut pid =
+                || *pid_cache.get_or_insert_with(|| window::get_window_process_id(window).ok());
+            let mut rect =
+                || *rect_cache.get_or_insert_with(|| window::get_window_rect(window).ok());
+            let value = match field {
+                Field::Hwnd => Some(Value::Number(handle::from_hwnd(window) as f64)),
+                Field::Pid => pid().map(|pid| Value::Number(pid as f64)),
+                Field::Exe => pid().and_then(|pid| builder.exe_path(pid)).and_then(|path| {
+                    Some(Value::String(path.file_name()?.to_string_lossy().into_owned()))
+                }),
+                Field::ExePath => pid()
+                    .and_then(|pid| builder.exe_path(pid))
+                    .map(|path| Value::String(path.to_string_lossy().into_owned())),
+                Field::Title => window::read_window_text(window).ok().flatten().map(Value::String),
+                Field::ClassName => window::get_class_name(window).ok().map(Value::String),
+                Field::X => rect().map(|rect| Value::Number(rect.left as f64)),
+                Field::Y => rect().map(|rect| Value::Number(rect.top as f64)),
+                Field::Width => rect().map(|rect| Value::Number((rect.right - rect.left) as f64)),
+                Field::Height => rect().map(|rect| Value::Number((rect.bottom - rect.top) as f64)),
+                Field::MonitorIndex => builder
+                    .monitor_index(window)
+                    .map(|index| Value::Number(index as f64)),
+            };
+            value.unwrap_or(Value::Null)
+        });
+        report.record("filterExpression", passed);
+    }
+}
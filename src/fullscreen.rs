@@ -0,0 +1,80 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use winapi::shared::windef::HWND;
+
+use crate::event::EventType;
+use crate::{handle, monitor};
+
+// How long a new fullscreen state must hold before it is reported, so a
+// window being resized or moved across the monitor edge doesn't flap.
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+// Turns samples of the foreground window's fullscreen state into debounced
+// `fullscreenEnter`/`fullscreenExit` transitions.
+pub struct FullscreenTracker {
+    // The window last reported as entering fullscreen, until it exits.
+    fullscreen_hwnd: Option<isize>,
+    pending: Option<Pending>,
+}
+
+// A change of fullscreen window waiting out `DEBOUNCE`.
+struct Pending {
+    // The window that will be fullscreen, or `None` for none.
+    hwnd: Option<isize>,
+    deadline: Instant,
+}
+
+impl FullscreenTracker {
+    pub fn new() -> Self {
+        Self {
+            fullscreen_hwnd: None,
+            pending: None,
+        }
+    }
+
+    // Sample the foreground window, after it changed or moved. Windows are
+    // compared, not just whether one is fullscreen, so the foreground moving
+    // straight from one fullscreen window to another is a change too.
+    pub fn observe(&mut self, window: HWND) {
+        let hwnd = Some(handle::from_hwnd(window)).filter(|_| monitor::is_fullscreen(window));
+        if hwnd == self.fullscreen_hwnd {
+            self.pending = None;
+            return;
+        }
+
+        match &self.pending {
+            // Keep the original deadline, so a sustained change is reported
+            // once it has held for `DEBOUNCE`.
+            Some(pending) if pending.hwnd == hwnd => {}
+            _ => {
+                self.pending = Some(Pending {
+                    hwnd,
+                    deadline: Instant::now() + DEBOUNCE,
+                });
+            }
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref().map(|pending| pending.deadline)
+    }
+
+    // The transition that has held for `DEBOUNCE` and the window it is
+    // about. An exit is about the window that was fullscreen, which isn't
+    // necessarily the current foreground window. Switching between two
+    // fullscreen windows is an exit for the old one, then, on the next call,
+    // an enter for the new one, which is already due.
+    pub fn expire(&mut self) -> Option<(HWND, EventType)> {
+        let pending = self.pending.take()?;
+        if let Some(hwnd) = self.fullscreen_hwnd.take() {
+            if pending.hwnd.is_some() {
+                self.pending = Some(pending);
+            }
+            return Some((handle::to_hwnd(hwnd), EventType::FullscreenExit));
+        }
+
+        let hwnd = pending.hwnd?;
+        self.fullscreen_hwnd = Some(hwnd);
+        Some((handle::to_hwnd(hwnd), EventType::FullscreenEnter))
+    }
+}
@@ -0,0 +1,108 @@
+// Conversions between `HWND`s and the integers and strings they cross the JS
+// boundary as. Everything that stores or receives a window handle goes
+// through here, so the event payloads, `listWindows()` and the control APIs
+// agree on the format.
+//
+// An `HWND` is an opaque handle, not a pointer: it's only ever passed back to
+// the OS and never dereferenced, so the casts below don't need to preserve
+// pointer provenance. 64-bit Windows only uses the low 32 bits of a window
+// handle (sign-extended), which is what makes a JS number able to hold one
+// exactly.
+
+use neon::prelude::*;
+use winapi::shared::windef::HWND;
+
+// The largest integer a JS number holds exactly.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
+pub fn from_hwnd(window: HWND) -> isize {
+    window as isize
+}
+
+pub fn to_hwnd(hwnd: isize) -> HWND {
+    hwnd as HWND
+}
+
+// The decimal string HWNDs are delivered as. Null is "0".
+pub fn to_string(hwnd: isize) -> String {
+    hwnd.to_string()
+}
+
+// Accept an HWND as the decimal string used in payloads or as an integer
+// number, throwing a `TypeError` for anything else.
+pub fn js_to_hwnd<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>) -> NeonResult<isize> {
+    if let Ok(value) = value.downcast::<JsString, _>(cx) {
+        let value = value.value(cx);
+        match from_str(&value) {
+            Some(hwnd) => Ok(hwnd),
+            None => cx.throw_type_error(format!("invalid HWND `{}`", value)),
+        }
+    } else if let Ok(value) = value.downcast::<JsNumber, _>(cx) {
+        let value = value.value(cx);
+        match from_number(value) {
+            Some(hwnd) => Ok(hwnd),
+            None => cx.throw_type_error(format!("invalid HWND `{}`", value)),
+        }
+    } else {
+        cx.throw_type_error("HWND must be a string or a number")
+    }
+}
+
+fn from_str(value: &str) -> Option<isize> {
+    value.trim().parse().ok()
+}
+
+// Only integers a JS number holds exactly, so a rounded value is never
+// mistaken for a different window.
+fn from_number(value: f64) -> Option<isize> {
+    if !value.is_finite() || value.fract() != 0.0 || value.abs() > MAX_SAFE_INTEGER {
+        return None;
+    }
+    Some(value as isize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn round_trips_null_and_extreme_handles() {
+        assert_eq!(from_hwnd(ptr::null_mut()), 0);
+        assert!(to_hwnd(0).is_null());
+        for hwnd in [0, 1, -1, 0x1234_5678, isize::MAX, isize::MIN] {
+            assert_eq!(from_hwnd(to_hwnd(hwnd)), hwnd);
+            assert_eq!(from_str(&to_string(hwnd)), Some(hwnd));
+        }
+        assert_eq!(to_string(0), "0");
+    }
+
+    #[test]
+    fn accepts_exact_integers_only() {
+        assert_eq!(from_number(0.0), Some(0));
+        assert_eq!(from_number(-0.0), Some(0));
+        assert_eq!(from_number(-1.0), Some(-1));
+        assert_eq!(from_number(MAX_SAFE_INTEGER), Some(9_007_199_254_740_991));
+        assert_eq!(from_number(-MAX_SAFE_INTEGER), Some(-9_007_199_254_740_991));
+
+        assert_eq!(from_number(MAX_SAFE_INTEGER + 1.0), None);
+        assert_eq!(from_number(-MAX_SAFE_INTEGER - 1.0), None);
+        assert_eq!(from_number(1.5), None);
+        assert_eq!(from_number(f64::NAN), None);
+        assert_eq!(from_number(f64::INFINITY), None);
+        assert_eq!(from_number(f64::NEG_INFINITY), None);
+    }
+
+    #[test]
+    fn parses_decimal_strings_only() {
+        assert_eq!(from_str(" 42 "), Some(42));
+        assert_eq!(from_str("-42"), Some(-42));
+        assert_eq!(from_str(&isize::MAX.to_string()), Some(isize::MAX));
+        assert_eq!(from_str(&isize::MIN.to_string()), Some(isize::MIN));
+
+        assert_eq!(from_str(&format!("{}0", isize::MAX)), None);
+        assert_eq!(from_str(""), None);
+        assert_eq!(from_str("0x10"), None);
+        assert_eq!(from_str("1.0"), None);
+    }
+}
@@ -0,0 +1,55 @@
+use std::{io, mem, time::Duration};
+
+use winapi::um::{
+    sysinfoapi::GetTickCount,
+    winuser::{GetLastInputInfo, LASTINPUTINFO},
+};
+
+use crate::event::{EventType, ForegroundEvent};
+
+// How often the idle time is sampled while an idle threshold is configured.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Time since the last keyboard or mouse input in this session.
+pub fn get_idle_time() -> io::Result<Duration> {
+    let mut info = LASTINPUTINFO {
+        cbSize: mem::size_of::<LASTINPUTINFO>() as u32,
+        dwTime: 0,
+    };
+    if unsafe { GetLastInputInfo(&mut info) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Both are tick counts, which wrap every ~49.7 days.
+    let now = unsafe { GetTickCount() };
+    Ok(Duration::from_millis(now.wrapping_sub(info.dwTime) as u64))
+}
+
+// Turns periodic idle-time samples into `idle`/`active` transitions.
+pub struct IdleTracker {
+    threshold: Duration,
+    is_idle: bool,
+}
+
+impl IdleTracker {
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            is_idle: false,
+        }
+    }
+
+    pub fn poll(&mut self) -> Option<ForegroundEvent> {
+        let idle = get_idle_time().ok()?;
+
+        if !self.is_idle && idle >= self.threshold {
+            self.is_idle = true;
+            Some(ForegroundEvent::idle(EventType::Idle, idle))
+        } else if self.is_idle && idle < self.threshold {
+            self.is_idle = false;
+            Some(ForegroundEvent::idle(EventType::Active, idle))
+        } else {
+            None
+        }
+    }
+}
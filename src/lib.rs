@@ -1,53 +1,156 @@
 use neon::prelude::*;
-use once_cell::sync::OnceCell;
-use std::sync::Arc;
+use neon::types::{buffer::TypedArray, Deferred};
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::{
     cell::RefCell,
-    io,
-    num::{NonZeroU32, NonZeroUsize},
-    ptr::{self, NonNull},
+    num::NonZeroU32,
+    thread,
+    time::Duration,
+};
+use tokio::runtime::{Handle as RuntimeHandle, Runtime};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    oneshot, watch,
 };
-use tokio::runtime::Runtime;
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
-use winapi::{
-    shared::windef::HWND,
-    um::{
-        errhandlingapi::{GetLastError, SetLastError},
-        winuser::{GetWindowTextLengthW, GetWindowTextW},
-    },
-};
-use wineventhook::{raw_event, AccessibleObjectId, EventFilter, WindowEventHook};
+use wineventhook::{raw_event, EventFilter, WindowEvent, WindowEventHook};
+
+use delta::DeltaEncoder;
+use diagnostics::DiagnosticSession;
+use error::{CallError, Error};
+use event::{EventType, ForegroundEvent};
+use ndjson::NdjsonWriter;
+use options::{PayloadFormat, StartOptions};
+use ordered::{EventSource, Sequence};
+use pipeline::Pipeline;
+use state::{ListenerState, SharedState};
+
+mod cause;
+mod com;
+mod delta;
+mod diagnostics;
+mod error;
+mod event;
+mod expr;
+mod filter;
+mod fullscreen;
+mod handle;
+mod idle;
+mod list;
+mod monitor;
+mod ndjson;
+mod options;
+mod ordered;
+mod pipeline;
+mod priority;
+mod process;
+mod session;
+mod shell;
+mod state;
+mod throttle;
+#[cfg(feature = "test-hooks")]
+mod test_hooks;
+mod track;
+#[cfg(feature = "uia")]
+mod uia;
+mod usage;
+mod value;
+mod window;
 
 type BoxedListener = JsBox<RefCell<WindowForegroundListener>>;
 
 struct WindowForegroundListener {
-    join_handle: Option<JoinHandle<()>>,
+    subscriptions: Vec<Subscription>,
+    state: SharedState,
+}
+
+// One listener task.
+struct Subscription {
+    stop_tx: oneshot::Sender<()>,
+    reconfigure_tx: UnboundedSender<StartOptions>,
+    // Set by `stop()` on the main thread, so a callback that is already
+    // queued there is skipped instead of running after `stop()` returned.
+    stopped: Arc<AtomicBool>,
 }
 
-impl Finalize for WindowForegroundListener {}
+// The receiving ends of a task's `Subscription`.
+struct Controls {
+    stop_rx: oneshot::Receiver<()>,
+    reconfigure_rx: UnboundedReceiver<StartOptions>,
+    stopped: Arc<AtomicBool>,
+}
+
+// What to start one listener task with.
+struct Config {
+    pid: u32,
+    options: StartOptions,
+    callbacks: Callbacks,
+}
+
+// Called when the JS object is garbage collected. Dropping the stop senders
+// would already end the tasks, but stopping explicitly keeps the shutdown path
+// identical to `stop()`. Only channel sends happen here, no JavaScript, so it
+// is safe to run during GC.
+impl Finalize for WindowForegroundListener {
+    fn finalize<'a, C: Context<'a>>(mut self, _cx: &mut C) {
+        self.stop();
+    }
+}
 
 impl WindowForegroundListener {
     fn new() -> Self {
-        Self { join_handle: None }
+        Self {
+            subscriptions: Vec::new(),
+            state: SharedState::default(),
+        }
     }
 
-    fn start(&mut self, rt: &Runtime, pid: u32, js_callback: JsCallback) {
+    // Replace whatever is running with one task per config, all sharing one
+    // state.
+    fn start(&mut self, rt: &GlobalRuntime, configs: Vec<Config>) {
         self.stop();
 
-        let join_handle = listen(rt, pid, js_callback);
-
-        self.join_handle = Some(join_handle);
+        let state = ListenerState::new(configs.iter().map(|config| &config.options));
+        for config in configs {
+            let (stop_tx, stop_rx) = oneshot::channel();
+            let (reconfigure_tx, reconfigure_rx) = mpsc::unbounded_channel();
+            let stopped = Arc::new(AtomicBool::new(false));
+            let controls = Controls {
+                stop_rx,
+                reconfigure_rx,
+                stopped: stopped.clone(),
+            };
+            listen(
+                rt,
+                config.pid,
+                config.options,
+                state.clone(),
+                config.callbacks,
+                controls,
+            );
+            self.subscriptions.push(Subscription {
+                stop_tx,
+                reconfigure_tx,
+                stopped,
+            });
+        }
+        self.state = state;
     }
 
+    // Signal the tasks to stop instead of aborting them. Aborting could drop a
+    // task in the middle of `JsCallback::call` or before `unhook`, leaving the
+    // OS hook installed. Each task stops waiting for the callback, which
+    // won't be called again once this returns, then unhooks and drops its
+    // `JsCallback`.
     fn stop(&mut self) {
-        match &self.join_handle {
-            Some(join_handle) => {
-                join_handle.abort();
-                self.join_handle = None;
-            }
-
-            _ => (),
+        for subscription in self.subscriptions.drain(..) {
+            subscription.stopped.store(true, Ordering::SeqCst);
+            let _ = subscription.stop_tx.send(());
         }
     }
 }
@@ -59,17 +162,265 @@ impl WindowForegroundListener {
         Ok(cx.boxed(RefCell::new(listener)))
     }
 
-    fn js_start(mut cx: FunctionContext) -> JsResult<JsUndefined> {
-        let rt = runtime(&mut cx)?;
-        let pid = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    // Arguments are all validated before the runtime is touched or the
+    // listener is stopped, so a bad call throws and leaves a running listener
+    // running.
+    fn js_start(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let pid = pid_argument(&mut cx, 0)?;
+        let callback = argument::<JsFunction>(&mut cx, 1, "callback", "a function")?;
+        let js_callback = JsCallback {
+            channel: cx.channel(),
+            callback: Arc::new(callback.root(&mut cx)),
+            this: None,
+        };
+
+        let options = cx.argument_opt(2);
+        Self::start_with(cx, pid, js_callback, None, options)
+    }
+
+    // Like `js_start`, but posts each event to a `MessagePort` (or anything
+    // with a `postMessage` method) instead of calling a callback.
+    //
+    // Neon can only run JavaScript on the thread that created the `Channel`,
+    // and `postMessage` has to be called there too, so events are posted from
+    // the thread that called this. Calling it from inside a worker keeps the
+    // main thread out of event delivery entirely.
+    fn js_start_port(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let pid = pid_argument(&mut cx, 0)?;
+        let port = argument::<JsObject>(&mut cx, 1, "port", "an object")?;
+        let post_message = port.get_value(&mut cx, "postMessage")?;
+        let post_message = match post_message.downcast::<JsFunction, _>(&mut cx) {
+            Ok(post_message) => post_message,
+            Err(_) => return cx.throw_type_error("`port` (argument 1) must have a `postMessage` method"),
+        };
+        let js_callback = JsCallback {
+            channel: cx.channel(),
+            callback: Arc::new(post_message.root(&mut cx)),
+            this: Some(Arc::new(port.root(&mut cx))),
+        };
+
+        let options = cx.argument_opt(2);
+        Self::start_with(cx, pid, js_callback, None, options)
+    }
+
+    // Follow one window: emits `foreground`/`foregroundLost` as it gains and
+    // loses the foreground, `locationChange` and `nameChange` as it is moved
+    // or renamed, and a final `destroy` before stopping on its own.
+    fn js_track_window(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let hwnd = argument::<JsValue>(&mut cx, 0, "hwnd", "a string or a number")?;
+        let hwnd = handle::js_to_hwnd(&mut cx, hwnd)?;
+        let callback = argument::<JsFunction>(&mut cx, 1, "callback", "a function")?;
+        let js_callback = JsCallback {
+            channel: cx.channel(),
+            callback: Arc::new(callback.root(&mut cx)),
+            this: None,
+        };
+
+        let options = cx.argument_opt(2);
+        Self::start_with(cx, 0, js_callback, Some(hwnd), options)
+    }
+
+    // Like `js_track_window`, for the window currently titled exactly
+    // `title`. The title is only used to find the window: it stays tracked
+    // after a rename. If several windows match, the topmost is tracked unless
+    // `options.multipleMatches` is "error".
+    fn js_track_window_by_title(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let title = argument::<JsString>(&mut cx, 0, "title", "a string")?.value(&mut cx);
+        let callback = argument::<JsFunction>(&mut cx, 1, "callback", "a function")?;
+        let options = cx.argument_opt(2);
+
+        let multiple_matches = match options.map(|options| options.downcast::<JsObject, _>(&mut cx)) {
+            Some(Ok(obj)) => options::get_string(&mut cx, obj, "multipleMatches")?,
+            _ => None,
+        };
+        let error_on_multiple = match multiple_matches.as_deref() {
+            None | Some("topmost") => false,
+            Some("error") => true,
+            Some(other) => {
+                return cx.throw_type_error(format!(
+                    "`multipleMatches` must be \"topmost\" or \"error\", got {:?}",
+                    other
+                ))
+            }
+        };
+
+        let matches = list::find_by_title(&title);
+        let hwnd = match matches.as_slice() {
+            [] => return cx.throw_error(format!("No window is titled {:?}", title)),
+            [_, _, ..] if error_on_multiple => {
+                return cx.throw_error(format!(
+                    "{} windows are titled {:?}",
+                    matches.len(),
+                    title
+                ))
+            }
+            [topmost, ..] => handle::from_hwnd(*topmost),
+        };
+
         let js_callback = JsCallback {
             channel: cx.channel(),
-            callback: Arc::new(cx.argument::<JsFunction>(1)?.root(&mut cx)),
+            callback: Arc::new(callback.root(&mut cx)),
+            this: None,
         };
 
+        Self::start_with(cx, 0, js_callback, Some(hwnd), options)
+    }
+
+    // Like `js_start`, but with everything in one object:
+    // `{ pid, callback, ...options }`. Unknown keys are reported with
+    // `process.emitWarning` rather than thrown, so code written against a
+    // newer version still starts.
+    fn js_start_config(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let config = argument::<JsObject>(&mut cx, 0, "config", "an object")?;
+        options::warn_unknown_keys(&mut cx, config, &["pid", "callback"])?;
+
+        let pid = match config.get_opt::<JsNumber, _, _>(&mut cx, "pid")? {
+            Some(pid) => {
+                let pid = pid.value(&mut cx);
+                check_pid(&mut cx, pid, "`config.pid`")?
+            }
+            None => 0,
+        };
+        let callback = config.get_value(&mut cx, "callback")?;
+        let callback = match callback.downcast::<JsFunction, _>(&mut cx) {
+            Ok(callback) => callback,
+            Err(_) => return cx.throw_type_error("`config.callback` must be a function"),
+        };
+        let js_callback = JsCallback {
+            channel: cx.channel(),
+            callback: Arc::new(callback.root(&mut cx)),
+            this: None,
+        };
+
+        Self::start_with(cx, pid, js_callback, None, Some(config.upcast()))
+    }
+
+    // Parse the options shared by the start variants and start.
+    //
+    // Returns a promise that resolves once the hooks are installed, or
+    // rejects with an `ERR_HOOK_FAILED` error if they can't be. It is settled
+    // through the callback's own channel before the task handles its first
+    // event, so everything the hooks see is buffered until then and the
+    // callback never runs before the promise has resolved.
+    fn start_with<'a>(
+        mut cx: FunctionContext<'a>,
+        pid: u32,
+        js_callback: JsCallback,
+        track_hwnd: Option<isize>,
+        options: Option<Handle<'a, JsValue>>,
+    ) -> JsResult<'a, JsPromise> {
+        check_interactive_desktop(&mut cx)?;
+        let on_close = JsCallback::from_option(&mut cx, options, "onClose")?;
+        let on_error = JsCallback::from_option(&mut cx, options, "onError")?;
+        let mut options = StartOptions::from_js(&mut cx, options)?;
+        options.track_hwnd = track_hwnd;
+        let ndjson = open_ndjson(&mut cx, &options)?;
+
         let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let rt = runtime(&mut cx)?;
+        let (ready, promise) = cx.promise();
+        let callbacks = Callbacks {
+            event: js_callback,
+            on_close,
+            on_error,
+            ndjson,
+            ready: Ready::Promise(ready),
+        };
         let mut listener = listener.borrow_mut();
-        listener.start(rt, pid, js_callback);
+        listener.start(
+            &rt,
+            vec![Config {
+                pid,
+                options,
+                callbacks,
+            }],
+        );
+
+        Ok(promise)
+    }
+
+    // Start one task per config, all delivering to the same callback. Each
+    // config is an options object with an optional `pid`, and its events carry
+    // its index in the array as `listenerId`.
+    //
+    // The configs share one history and `ignoreHwnds` set, and `stop()` stops
+    // all of them. Returns a promise for the ids, see `StartGroup`. Every
+    // config's events go through one channel, the one the promise is settled
+    // through, so none reaches the callback before it has resolved.
+    fn js_start_many(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        check_interactive_desktop(&mut cx)?;
+        let callback = argument::<JsFunction>(&mut cx, 0, "callback", "a function")?;
+        let callback = Arc::new(callback.root(&mut cx));
+        let channel = cx.channel();
+        let values = argument::<JsArray>(&mut cx, 1, "configs", "an array")?.to_vec(&mut cx)?;
+        let group = StartGroup::new(values.len(), channel.clone());
+
+        let mut configs = Vec::with_capacity(values.len());
+        for (i, value) in values.into_iter().enumerate() {
+            let obj = match value.downcast::<JsObject, _>(&mut cx) {
+                Ok(obj) => obj,
+                Err(_) => return cx.throw_type_error(format!("`configs[{}]` must be an object", i)),
+            };
+            let pid = match obj.get_opt::<JsNumber, _, _>(&mut cx, "pid")? {
+                Some(pid) => {
+                    let pid = pid.value(&mut cx);
+                    check_pid(&mut cx, pid, &format!("`configs[{}].pid`", i))?
+                }
+                None => 0,
+            };
+            let on_close = JsCallback::from_option(&mut cx, Some(value), "onClose")?;
+            let on_error = JsCallback::from_option(&mut cx, Some(value), "onError")?;
+            let mut options = StartOptions::from_js(&mut cx, Some(value))?;
+            options.listener_id = Some(i as u32);
+            let event = JsCallback {
+                channel: channel.clone(),
+                callback: callback.clone(),
+                this: None,
+            };
+
+            let ndjson = open_ndjson(&mut cx, &options)?;
+
+            configs.push(Config {
+                pid,
+                options,
+                callbacks: Callbacks {
+                    event,
+                    on_close,
+                    on_error,
+                    ndjson,
+                    ready: Ready::Group(group.clone()),
+                },
+            });
+        }
+
+        let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let rt = runtime(&mut cx)?;
+        let (ready, promise) = cx.promise();
+        group.attach(ready);
+        let mut listener = listener.borrow_mut();
+        listener.start(&rt, configs);
+
+        Ok(promise)
+    }
+
+    // Swap the options of the running listener without reinstalling its hooks.
+    // The new options are fully validated first, so a bad call throws and
+    // leaves the current options in place. Applies to every subscription of a
+    // `listenerStartMany` listener.
+    fn js_reconfigure(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let options = cx.argument_opt(0);
+        let options = StartOptions::from_js(&mut cx, options)?;
+
+        let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let listener = listener.borrow();
+        if listener.subscriptions.is_empty() {
+            return cx.throw_error("The listener isn't running");
+        }
+
+        listener.state.lock().unwrap().reconfigure(&options);
+        for subscription in &listener.subscriptions {
+            let _ = subscription.reconfigure_tx.send(options.clone());
+        }
 
         Ok(cx.undefined())
     }
@@ -81,127 +432,1164 @@ impl WindowForegroundListener {
         listener.stop();
         Ok(cx.undefined())
     }
+
+    // Replace the set of ignored HWNDs on the running session.
+    fn js_set_ignored_hwnds(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let hwnds = cx.argument::<JsArray>(0)?;
+        let hwnds = options::hwnd_set_from_js(&mut cx, hwnds)?;
+
+        let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let state = listener.borrow().state.clone();
+        state.lock().unwrap().ignored_hwnds = hwnds;
+
+        Ok(cx.undefined())
+    }
+
+    // The last event delivered to the callback, or `null` before the first.
+    fn js_get_last(mut cx: FunctionContext) -> JsResult<JsValue> {
+        let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let state = listener.borrow().state.clone();
+        let state = state.lock().unwrap();
+
+        match &state.last {
+            Some(event) => event.to_js(&mut cx),
+            None => Ok(cx.null().upcast()),
+        }
+    }
+
+    // Foreground milliseconds per process or executable, with `usageStatsBy`.
+    // `null` if usage isn't being tracked.
+    fn js_get_usage_stats(mut cx: FunctionContext) -> JsResult<JsValue> {
+        let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let options = cx.argument_opt(0);
+        let detailed = match options.map(|options| options.downcast::<JsObject, _>(&mut cx)) {
+            Some(Ok(obj)) => options::get_bool(&mut cx, obj, "detailed")?.unwrap_or(false),
+            _ => false,
+        };
+        let state = listener.borrow().state.clone();
+        let state = state.lock().unwrap();
+
+        match &state.usage {
+            Some(usage) if detailed => value::to_js(&mut cx, &usage.detailed_snapshot()),
+            Some(usage) => value::to_js(&mut cx, &usage.snapshot()),
+            None => Ok(cx.null().upcast()),
+        }
+    }
+
+    fn js_reset_usage_stats(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+        let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let state = listener.borrow().state.clone();
+        if let Some(usage) = &mut state.lock().unwrap().usage {
+            usage.reset();
+        }
+
+        Ok(cx.undefined())
+    }
+
+    // Record what the listener sees for `durationMs`, logging it to stderr
+    // as well with `{ log: true }`, then resolve with the report. The session
+    // belongs to the state of the listener running now; restarting the
+    // listener ends its recording early.
+    fn js_start_diagnostic_session(mut cx: FunctionContext) -> JsResult<JsPromise> {
+        let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let duration = argument::<JsNumber>(&mut cx, 0, "durationMs", "a number")?.value(&mut cx);
+        if !duration.is_finite() || duration <= 0.0 {
+            return cx.throw_range_error("`durationMs` (argument 0) must be a positive number");
+        }
+        let options = cx.argument_opt(1);
+        let log = match options.map(|options| options.downcast::<JsObject, _>(&mut cx)) {
+            Some(Ok(obj)) => options::get_bool(&mut cx, obj, "log")?.unwrap_or(false),
+            _ => false,
+        };
+        let rt = runtime(&mut cx)?;
+
+        let state = listener.borrow().state.clone();
+        {
+            let mut state = state.lock().unwrap();
+            if state.diagnostics.is_some() {
+                return cx.throw_error("A diagnostic session is already running");
+            }
+            let dropped = DROPPED_EVENTS.load(Ordering::SeqCst);
+            state.diagnostics = Some(DiagnosticSession::new(dropped, log));
+        }
+
+        let channel = cx.channel();
+        let (deferred, promise) = cx.promise();
+        rt.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(duration as u64)).await;
+            let session = state.lock().unwrap().diagnostics.take();
+            let dropped = DROPPED_EVENTS.load(Ordering::SeqCst);
+            let report = session.map(|session| session.into_report(dropped));
+            deferred.settle_with(&channel, move |mut cx| value::to_js(&mut cx, &report));
+        });
+
+        Ok(promise)
+    }
+
+    fn js_get_history(mut cx: FunctionContext) -> JsResult<JsArray> {
+        let listener = cx.this().downcast_or_throw::<BoxedListener, _>(&mut cx)?;
+        let state = listener.borrow().state.clone();
+        let state = state.lock().unwrap();
+
+        let history = cx.empty_array();
+        for (i, event) in state.history().enumerate() {
+            let event = event.to_js(&mut cx)?;
+            history.set(&mut cx, i as u32, event)?;
+        }
+
+        Ok(history)
+    }
 }
 
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("listenerNew", WindowForegroundListener::js_new)?;
     cx.export_function("listenerStart", WindowForegroundListener::js_start)?;
+    cx.export_function("listenerStartPort", WindowForegroundListener::js_start_port)?;
+    cx.export_function("listenerStartConfig", WindowForegroundListener::js_start_config)?;
+    cx.export_function("listenerStartMany", WindowForegroundListener::js_start_many)?;
+    cx.export_function("listenerTrackWindow", WindowForegroundListener::js_track_window)?;
+    cx.export_function(
+        "listenerTrackWindowByTitle",
+        WindowForegroundListener::js_track_window_by_title,
+    )?;
     cx.export_function("listenerStop", WindowForegroundListener::js_stop)?;
+    cx.export_function("listenerReconfigure", WindowForegroundListener::js_reconfigure)?;
+    cx.export_function("listenerGetHistory", WindowForegroundListener::js_get_history)?;
+    cx.export_function("listenerGetLast", WindowForegroundListener::js_get_last)?;
+    cx.export_function("listenerGetUsageStats", WindowForegroundListener::js_get_usage_stats)?;
+    cx.export_function(
+        "listenerResetUsageStats",
+        WindowForegroundListener::js_reset_usage_stats,
+    )?;
+    cx.export_function(
+        "listenerStartDiagnosticSession",
+        WindowForegroundListener::js_start_diagnostic_session,
+    )?;
+    cx.export_function(
+        "listenerSetIgnoredHwnds",
+        WindowForegroundListener::js_set_ignored_hwnds,
+    )?;
+    cx.export_function("stopAll", js_stop_all)?;
+    cx.export_function("shutdownRuntime", js_shutdown_runtime)?;
+    cx.export_function("activeHookCount", js_active_hook_count)?;
+    cx.export_function("droppedEventCount", js_dropped_event_count)?;
+    cx.export_function("getCapabilities", js_get_capabilities)?;
+    cx.export_function("listWindows", js_list_windows)?;
+    #[cfg(feature = "test-hooks")]
+    test_hooks::export(&mut cx)?;
     Ok(())
 }
 
-// Return a global tokio runtime or create one if it doesn't exist.
+// Which optional features this build supports, so consumers can feature-detect
+// instead of relying on the package version.
+fn js_get_capabilities(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let capabilities = cx.empty_object();
+
+    let raw_handles = cx.boolean(true);
+    capabilities.set(&mut cx, "rawHandles", raw_handles)?;
+
+    // See `inContext` in `StartOptions::from_js`.
+    let in_context_hooks = cx.boolean(false);
+    capabilities.set(&mut cx, "inContextHooks", in_context_hooks)?;
+
+    // `false` in a service or on a non-interactive window station, where
+    // starting a listener throws.
+    let interactive_desktop = cx.boolean(session::has_interactive_desktop());
+    capabilities.set(&mut cx, "interactiveDesktop", interactive_desktop)?;
+
+    // `{ uia: bool, "process-info": bool }`: which Cargo features this build
+    // was compiled with. Options needing a missing one throw.
+    let features = cx.empty_object();
+    for &feature in options::FEATURES {
+        let compiled_in = cx.boolean(options::is_compiled_in(feature));
+        features.set(&mut cx, feature, compiled_in)?;
+    }
+    capabilities.set(&mut cx, "features", features)?;
+
+    Ok(capabilities)
+}
+
+// Visible top-level windows, front to back, optionally only those on one
+// monitor: `listWindows({ monitorIndex })`.
+fn js_list_windows(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let monitor_index = match cx.argument_opt(0) {
+        Some(options) => match options.downcast::<JsObject, _>(&mut cx) {
+            Ok(options) => options::get_index(&mut cx, options, "monitorIndex")?,
+            Err(_) => None,
+        },
+        None => None,
+    };
+
+    value::to_js(&mut cx, &list::list_windows(monitor_index))
+}
+
+// Number of OS hooks currently installed by this module, across all listeners.
+// Should return to zero once every listener has stopped.
+static ACTIVE_HOOKS: AtomicUsize = AtomicUsize::new(0);
+
+fn js_active_hook_count(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    Ok(cx.number(ACTIVE_HOOKS.load(Ordering::SeqCst) as f64))
+}
+
+// Number of events that never reached a callback because they couldn't be
+// queued to the main thread, even after any `callbackRetries`, across all
+// listeners.
+static DROPPED_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+fn js_dropped_event_count(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    Ok(cx.number(DROPPED_EVENTS.load(Ordering::SeqCst) as f64))
+}
+
+// Fetch a required argument, throwing a `TypeError` that names it if it is
+// missing or of the wrong type.
+fn argument<'a, V: Value>(
+    cx: &mut FunctionContext<'a>,
+    i: i32,
+    name: &str,
+    expected: &str,
+) -> JsResult<'a, V> {
+    if let Some(value) = cx.argument_opt(i) {
+        if let Ok(value) = value.downcast::<V, _>(cx) {
+            return Ok(value);
+        }
+    }
+
+    cx.throw_type_error(format!("`{}` (argument {}) must be {}", name, i, expected))
+}
+
+// Checked before anything else when starting, since no arguments can make a
+// listener work without a desktop.
+fn check_interactive_desktop<'a, C: Context<'a>>(cx: &mut C) -> NeonResult<()> {
+    if session::has_interactive_desktop() {
+        Ok(())
+    } else {
+        Error::NoInteractiveDesktop.throw(cx)
+    }
+}
+
+fn pid_argument(cx: &mut FunctionContext, i: i32) -> NeonResult<u32> {
+    let pid = argument::<JsNumber>(cx, i, "pid", "a number")?.value(cx);
+    check_pid(cx, pid, &format!("`pid` (argument {})", i))
+}
+
+// Open `ndjsonPath` up front, so a bad path throws from the start call instead
+// of failing silently on the listener task.
+fn open_ndjson<'a, C: Context<'a>>(
+    cx: &mut C,
+    options: &StartOptions,
+) -> NeonResult<Option<NdjsonWriter>> {
+    match &options.ndjson_path {
+        Some(path) => match NdjsonWriter::open(path) {
+            Ok(writer) => Ok(Some(writer)),
+            Err(err) => err.throw(cx),
+        },
+        None => Ok(None),
+    }
+}
+
+fn check_pid<'a, C: Context<'a>>(cx: &mut C, pid: f64, name: &str) -> NeonResult<u32> {
+    if pid < 0.0 || pid > u32::MAX as f64 || pid.fract() != 0.0 {
+        return cx.throw_range_error(format!(
+            "{} must be a process id, or 0 for all processes",
+            name
+        ));
+    }
+
+    Ok(pid as u32)
+}
+
+// Threading model:
+//
+// - All JavaScript runs on the thread that called `listenerStart` (usually the
+//   main thread). Native code only reaches it through a `Channel`.
+// - Every listener task runs on the one global tokio runtime below. Tasks
+//   never share `Root`s or `Channel`s with one another, and never call into
+//   JavaScript directly.
+// - The hooks themselves are owned by `wineventhook`'s message-loop thread,
+//   which forwards events to the task over an mpsc channel.
+//
+// Listeners must not be spawned on any other runtime: a second runtime means a
+// second pool of worker threads that nothing shuts down, and JS-facing state
+// like `activeHookCount` assumes every task lives here. Tasks are spawned
+// through `GlobalRuntime`, which only `runtime()` hands out.
+//
+// `shutdownRuntime` takes the runtime out again, and the next start creates a
+// new one.
+static RUNTIME: Lazy<Mutex<Option<Runtime>>> = Lazy::new(|| Mutex::new(None));
+
+// A handle to the global runtime. Only `runtime()` makes one, so a listener
+// can't be spawned anywhere else.
+#[derive(Clone)]
+struct GlobalRuntime(RuntimeHandle);
+
+impl GlobalRuntime {
+    fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.0.spawn(future)
+    }
+}
+
+// Return the global tokio runtime or create one if it doesn't exist.
 // Throws a JavaScript exception if the `Runtime` fails to create.
-fn runtime<'a, C: Context<'a>>(cx: &mut C) -> NeonResult<&'static Runtime> {
-    static RUNTIME: OnceCell<Runtime> = OnceCell::new();
-
-    RUNTIME.get_or_try_init(|| Runtime::new().or_else(|err| cx.throw_error(err.to_string())))
-}
-
-fn listen(rt: &Runtime, pid: u32, js_callback: JsCallback) -> JoinHandle<()> {
-    return rt.spawn(async move {
-        let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
-        let filter = EventFilter::default().event(raw_event::SYSTEM_FOREGROUND);
-        let filter = match NonZeroU32::new(pid) {
-            Some(pid) => filter.process(pid),
-            _ => filter,
-        };
-
-        let hook = WindowEventHook::hook(filter, event_tx).await.unwrap();
-
-        while let Some(event) = event_rx.recv().await {
-            if event.object_type() == AccessibleObjectId::Window {
-                let hwnd = format!(
-                    "{}",
-                    (event
-                        .window_handle()
-                        .map_or_else(ptr::null_mut, NonNull::as_ptr)) as isize
-                );
-
-                let result = js_callback.call(hwnd).await;
-                // let title = get_window_text(
-                //     event
-                //         .window_handle()
-                //         .map_or_else(ptr::null_mut, NonNull::as_ptr),
-                // )
-                // .unwrap();
-                // let result = match title {
-                //     Some(v) => js_callback.call(v).await,
-                //     None => js_callback.call(String::new()).await,
-                // };
-
-                match result {
-                    Err(err) => println!("Failed to call JavaScript: {:?}", err),
-                    _ => (),
+//
+// The exception is thrown once the lock has been released. A failed init
+// leaves the runtime unset, so the next call simply tries again, and nothing
+// touches JavaScript while the lock is held.
+fn runtime<'a, C: Context<'a>>(cx: &mut C) -> NeonResult<GlobalRuntime> {
+    let handle = {
+        let mut global = RUNTIME.lock().unwrap();
+        match &*global {
+            Some(rt) => Ok(rt.handle().clone()),
+            None => new_runtime().map(|rt| {
+                let handle = rt.handle().clone();
+                *global = Some(rt);
+                handle
+            }),
+        }
+    };
+
+    match handle {
+        Ok(handle) => Ok(GlobalRuntime(handle)),
+        Err(err) => Error::RuntimeInit(err).throw(cx),
+    }
+}
+
+// The same runtime `Runtime::new()` makes. `test-hooks` builds mark its
+// threads for `testTaskRuntimes()`.
+fn new_runtime() -> std::io::Result<Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    #[cfg(feature = "test-hooks")]
+    {
+        if let Some(err) = test_hooks::runtime_init_failure() {
+            return Err(err);
+        }
+        let generation = test_hooks::next_runtime_generation();
+        builder.on_thread_start(move || test_hooks::enter_runtime_thread(generation));
+    }
+    builder.build()
+}
+
+// Bumped by `stopAll` and `shutdownRuntime` to stop every listener task,
+// whichever listener object it belongs to.
+static STOP_ALL: Lazy<watch::Sender<u64>> = Lazy::new(|| watch::channel(0).0);
+
+// Listener tasks that haven't finished yet, including unhooking.
+static LIVE_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Counts a task in `LIVE_TASKS` until it finishes or is dropped.
+struct LiveTask;
+
+impl LiveTask {
+    fn new() -> Self {
+        LIVE_TASKS.fetch_add(1, Ordering::SeqCst);
+        LiveTask
+    }
+}
+
+impl Drop for LiveTask {
+    fn drop(&mut self) {
+        LIVE_TASKS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Signal every listener task to stop and wait up to `timeout` for all of them
+// to unhook and finish. Blocks, so it must run off both the main thread, whose
+// callbacks the tasks may be waiting on, and the runtime. Returns whether
+// they all finished in time.
+fn stop_all_tasks(timeout: Duration) -> bool {
+    STOP_ALL.send_modify(|generation| *generation += 1);
+
+    let deadline = std::time::Instant::now() + timeout;
+    while LIVE_TASKS.load(Ordering::SeqCst) > 0 {
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(DRAIN_POLL_INTERVAL);
+    }
+    true
+}
+
+// `stopAll(timeoutMs)`: stop every listener, resolving with whether they all
+// unhooked within the timeout.
+fn js_stop_all(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let timeout = shutdown_timeout_argument(&mut cx)?;
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    thread::spawn(move || {
+        let stopped = stop_all_tasks(timeout);
+        deferred.settle_with(&channel, move |mut cx| Ok(cx.boolean(stopped)));
+    });
+
+    Ok(promise)
+}
+
+// `shutdownRuntime(timeoutMs)`: stop every listener and wait for them to
+// unhook first, since shutting the runtime down cancels whatever a task is
+// awaiting, unhooking included. The runtime then gets what is left of the
+// timeout to shut down, and the next start creates a new one. Resolves with
+// whether every listener stopped cleanly.
+fn js_shutdown_runtime(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let timeout = shutdown_timeout_argument(&mut cx)?;
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    thread::spawn(move || {
+        let started = std::time::Instant::now();
+        let stopped = stop_all_tasks(timeout);
+        let rt = RUNTIME.lock().unwrap().take();
+        if let Some(rt) = rt {
+            rt.shutdown_timeout(timeout.saturating_sub(started.elapsed()));
+        }
+        deferred.settle_with(&channel, move |mut cx| Ok(cx.boolean(stopped)));
+    });
+
+    Ok(promise)
+}
+
+fn shutdown_timeout_argument(cx: &mut FunctionContext) -> NeonResult<Duration> {
+    let timeout = match cx.argument_opt(0) {
+        Some(value) if !value.is_a::<JsUndefined, _>(cx) => {
+            argument::<JsNumber>(cx, 0, "timeoutMs", "a number")?.value(cx)
+        }
+        _ => return Ok(DEFAULT_SHUTDOWN_TIMEOUT),
+    };
+    if !timeout.is_finite() || timeout < 0.0 {
+        return cx.throw_range_error("`timeoutMs` (argument 0) must be a non-negative number");
+    }
+
+    Ok(Duration::from_millis(timeout as u64))
+}
+
+fn listen(
+    rt: &GlobalRuntime,
+    pid: u32,
+    options: StartOptions,
+    state: SharedState,
+    callbacks: Callbacks,
+    controls: Controls,
+) {
+    let live = LiveTask::new();
+    let mut stop_all = STOP_ALL.subscribe();
+
+    rt.spawn(async move {
+        let _live = live;
+        #[cfg(feature = "test-hooks")]
+        let _runtime = test_hooks::TaskRuntime::enter();
+        let Controls {
+            mut stop_rx,
+            mut reconfigure_rx,
+            stopped,
+        } = controls;
+        let js_callback = callbacks.event;
+        let ready = callbacks.ready;
+        let mut ndjson = callbacks.ndjson;
+        let mut close = CloseNotifier::new(callbacks.on_close);
+        let on_error = callbacks.on_error;
+        let diagnostics = state.clone();
+        let report = |err: &Error| {
+            println!("{}", err);
+            if let Some(on_error) = &on_error {
+                on_error.notify(err.report());
+            }
+            state::diagnose(&diagnostics, |session| session.record_error(err.report()));
+        };
+
+        let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut events = EventSource::new(event_rx, options.strict_order);
+        let filters = match options.track_hwnd {
+            Some(hwnd) => track::filters(handle::to_hwnd(hwnd)),
+            None => {
+                let scoped = |event| {
+                    let mut filter = EventFilter::default().event(event);
+                    if let Some(pid) = NonZeroU32::new(pid) {
+                        filter = filter.process(pid);
+                    }
+                    if let Some(thread_id) = options.hook_thread_id {
+                        filter = filter.thread(thread_id);
+                    }
+                    filter
+                };
+                let mut filters = vec![scoped(raw_event::SYSTEM_FOREGROUND)];
+                if options.fullscreen_events {
+                    filters.push(scoped(raw_event::OBJECT_LOCATIONCHANGE));
+                }
+                filters
+            }
+        };
+
+        let hooks = match install_hooks_with_retry(filters, event_tx).await {
+            Ok(hooks) => hooks,
+            Err(err) => {
+                report(&err);
+                match ready {
+                    Ready::Promise(ready) => {
+                        ready.settle_with(
+                            &js_callback.channel,
+                            move |mut cx| -> JsResult<JsUndefined> { err.throw(&mut cx) },
+                        );
+                    }
+                    Ready::Group(group) => group.fail(err),
+                }
+                close.fire(CloseReason::HookFailed);
+                return;
+            }
+        };
+
+        // Queued ahead of any event on the same channel, so the promise
+        // resolves before the callback first runs.
+        match ready {
+            Ready::Promise(ready) => {
+                ready.settle_with(&js_callback.channel, |mut cx| Ok(cx.undefined()));
+            }
+            Ready::Group(group) => {
+                // Another config's hooks failed, which rejected the promise.
+                if !group.installed().await {
+                    unhook_all(hooks).await;
+                    close.fire(CloseReason::Stopped);
+                    return;
                 }
+            }
+        }
 
-                ()
+        // Hooks can be handed a burst of queued events as soon as they go
+        // live, which aren't real transitions.
+        let quiet_until = options.startup_quiet.map(|quiet| Instant::now() + quiet);
+        let is_quiet = || quiet_until.is_some_and(|until| Instant::now() < until);
+        let max_events = options.max_events;
+        let ndjson_only = options.ndjson_only;
+        let mut delivery = Delivery::from_options(&options, stopped);
+        let mut delivered = 0;
+        // `strictOrder` drops not yet reported in a delivered event's
+        // `droppedBefore`.
+        let mut dropped_before = 0;
+        let mut close_reason = CloseReason::Stopped;
+        // `None` if not requested, or if session notifications aren't
+        // available, in which case the branch below never fires.
+        let mut session_rx = if options.session_events {
+            session::subscribe()
+        } else {
+            None
+        };
+        // Checked once up front, so a level the process may not use is
+        // reported instead of silently ignored on every event.
+        let thread_priority = options.thread_priority;
+        if let Some(priority) = thread_priority {
+            if let Err(err) = priority::PriorityGuard::new(priority) {
+                report(&Error::from(err));
             }
         }
+        let mut pipeline = Pipeline::new(options, state);
+        let mut idle_interval = tokio::time::interval(idle::POLL_INTERVAL);
+
+        loop {
+            let deadline = pipeline.deadline();
+
+            let input = tokio::select! {
+                // Either an explicit stop or the listener being dropped.
+                _ = &mut stop_rx => break,
+                _ = stop_all.changed() => break,
+                event = events.recv() => match event {
+                    Some((_, sequence)) if is_quiet() => {
+                        dropped_before += sequence.map_or(0, |sequence| sequence.dropped_before);
+                        continue;
+                    }
+                    Some((event, sequence)) => Input::Window(event, sequence),
+                    None => break,
+                },
+                Some(options) = reconfigure_rx.recv() => Input::Reconfigure(Box::new(options)),
+                Some(change) = recv_session(&mut session_rx) => Input::Session(change),
+                _ = idle_interval.tick(), if pipeline.tracks_idle() => Input::IdleTick,
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+                    if deadline.is_some() => Input::Deadline,
+            };
+
+            let received = Instant::now();
+            let sequence = match &input {
+                Input::Window(_, sequence) => *sequence,
+                _ => None,
+            };
+            dropped_before += sequence.map_or(0, |sequence| sequence.dropped_before);
+            if let Input::Window(event, _) = &input {
+                state::diagnose(&diagnostics, |session| {
+                    let raw = &event.raw;
+                    let hwnd = handle::to_string(handle::from_hwnd(raw.window_handle));
+                    session.record_raw(raw.event_id, hwnd, raw.object_id);
+                });
+            }
+
+            // Processing runs at `threadPriority`, but waiting and calling back
+            // don't, see `PriorityGuard`.
+            let payload = {
+                let _priority = thread_priority
+                    .and_then(|priority| priority::PriorityGuard::new(priority).ok());
+
+                let payload = match input {
+                    Input::Window(event, _) => pipeline.on_window_event(&event),
+                    Input::Reconfigure(options) => {
+                        pipeline.reconfigure(*options);
+                        None
+                    }
+                    Input::Session(change) => pipeline.on_session_change(change),
+                    Input::IdleTick => pipeline.on_idle_tick(),
+                    Input::Deadline => pipeline.on_deadline(),
+                };
+                let mut payload = match payload {
+                    Some(payload) => payload,
+                    None => continue,
+                };
+
+                pipeline.on_deliver(&mut payload);
+                // Nothing holds events back under `strictOrder`, so this is
+                // the payload for the WinEvent just received.
+                if let Some(sequence) = sequence {
+                    payload.seq = Some(sequence.seq);
+                    payload.dropped_before = Some(std::mem::take(&mut dropped_before));
+                }
+                payload
+            };
+
+            // A tracked window is gone for good once destroyed.
+            let is_final = payload.event_type == EventType::Destroy;
+
+            if let Some(writer) = &mut ndjson {
+                if let Err(err) = writer.write(&payload) {
+                    // Stop writing rather than failing on every event.
+                    report(&err);
+                    ndjson = None;
+                }
+            }
 
-        hook.unhook().await.unwrap();
+            let (event_type, hwnd) = (payload.event_type, payload.hwnd);
+            let result = if ndjson_only {
+                Ok(CallbackControl::Continue)
+            } else {
+                js_callback.deliver(payload, &mut delivery).await
+            };
+            state::diagnose(&diagnostics, |session| {
+                session.record_delivered(
+                    event_type,
+                    handle::to_string(hwnd.unwrap_or(0)),
+                    received.elapsed().as_micros() as u64,
+                    result.as_ref().err().map(|err| err.to_string()),
+                )
+            });
+
+            let is_delivered = match result {
+                Ok(CallbackControl::Stop) => break,
+                Ok(CallbackControl::Continue) => true,
+                Err(err) => {
+                    if let CallError::NotQueued = err {
+                        DROPPED_EVENTS.fetch_add(1, Ordering::SeqCst);
+                    }
+                    println!("{}", Error::from(err));
+                    false
+                }
+            };
+
+            if is_final {
+                break;
+            }
+
+            // Only delivered events count, not ones that were filtered out,
+            // dropped or failed.
+            if is_delivered {
+                delivered += 1;
+                if max_events.is_some_and(|max_events| delivered >= max_events) {
+                    close_reason = CloseReason::MaxEvents;
+                    break;
+                }
+            }
+        }
+
+        unhook_all(hooks).await;
+        pipeline.finish();
+
+        if let Some(writer) = &mut ndjson {
+            if let Err(err) = writer.flush() {
+                report(&err);
+            }
+        }
+
+        close.fire(close_reason);
     });
 }
 
-// fn get_window_text_length(window: HWND) -> io::Result<Option<NonZeroUsize>> {
-//     unsafe { SetLastError(0) };
-//     let result = unsafe { GetWindowTextLengthW(window) };
-//     if result == 0 && unsafe { GetLastError() } != 0 {
-//         Err(io::Error::last_os_error())
-//     } else {
-//         Ok(NonZeroUsize::new(result as usize))
-//     }
-// }
-
-// fn get_window_text(window: HWND) -> io::Result<Option<String>> {
-//     let text_len = if let Some(length) = get_window_text_length(window)? {
-//         length.get()
-//     } else {
-//         return Ok(None);
-//     };
-
-//     let mut text = Vec::with_capacity(text_len + 1); // +1 for null terminator
-//     let result = unsafe { GetWindowTextW(window, text.as_mut_ptr(), text.capacity() as i32) };
-//     if result != 0 {
-//         unsafe { text.set_len(text_len) };
-//         let text = String::from_utf16_lossy(&text);
-//         Ok(Some(text))
-//     } else {
-//         Err(io::Error::last_os_error())
-//     }
-// }
+// What woke the listener task up.
+enum Input {
+    // With its `Sequence` under `strictOrder`.
+    Window(WindowEvent, Option<Sequence>),
+    Reconfigure(Box<StartOptions>),
+    Session(session::SessionChange),
+    IdleTick,
+    Deadline,
+}
+
+async fn recv_session(
+    session_rx: &mut Option<UnboundedReceiver<session::SessionChange>>,
+) -> Option<session::SessionChange> {
+    match session_rx {
+        Some(session_rx) => session_rx.recv().await,
+        None => None,
+    }
+}
+
+// Attempts at installing the hooks, and the delay before the first retry,
+// which doubles each time.
+const HOOK_ATTEMPTS: u32 = 4;
+const HOOK_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// Retry retriable failures a few times before giving up, for machines that
+// are briefly out of resources. A stop during the retries takes effect once
+// they're over.
+async fn install_hooks_with_retry(
+    filters: Vec<EventFilter>,
+    event_tx: UnboundedSender<WindowEvent>,
+) -> error::Result<Vec<WindowEventHook>> {
+    let mut delay = HOOK_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match install_hooks(filters.clone(), event_tx.clone()).await {
+            Err(err) if err.is_retriable() && attempt < HOOK_ATTEMPTS => {
+                println!("{}, retrying", err);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Install one hook per filter, all feeding the same channel. If any fails, the
+// ones already installed are removed again.
+async fn install_hooks(
+    filters: Vec<EventFilter>,
+    event_tx: UnboundedSender<WindowEvent>,
+) -> error::Result<Vec<WindowEventHook>> {
+    let mut hooks = Vec::with_capacity(filters.len());
+    for filter in filters {
+        match WindowEventHook::hook(filter, event_tx.clone()).await {
+            Ok(hook) => {
+                ACTIVE_HOOKS.fetch_add(1, Ordering::SeqCst);
+                hooks.push(hook);
+            }
+            Err(err) => {
+                unhook_all(hooks).await;
+                return Err(Error::HookFailed(err));
+            }
+        }
+    }
+
+    Ok(hooks)
+}
+
+async fn unhook_all(hooks: Vec<WindowEventHook>) {
+    for hook in hooks {
+        match hook.unhook().await {
+            Ok(()) => {
+                ACTIVE_HOOKS.fetch_sub(1, Ordering::SeqCst);
+            }
+            Err(err) => println!("{}", Error::UnhookFailed(err)),
+        }
+    }
+}
+
+// How a listener hands events to its callback, fixed when it starts.
+pub struct Delivery {
+    retries: u32,
+    retry_delay: Duration,
+    timeout: Option<Duration>,
+    format: PayloadFormat,
+    delta: Option<DeltaEncoder>,
+    // The subscription's `stopped` flag.
+    stopped: Arc<AtomicBool>,
+}
+
+impl Delivery {
+    fn from_options(options: &StartOptions, stopped: Arc<AtomicBool>) -> Self {
+        Self {
+            retries: options.callback_retries,
+            retry_delay: options.callback_retry_delay,
+            timeout: options.callback_timeout,
+            format: options.payload_format,
+            delta: if options.delta {
+                Some(DeltaEncoder::default())
+            } else {
+                None
+            },
+            stopped,
+        }
+    }
+}
+
+// An event in the form the callback receives it.
+#[derive(Clone)]
+enum CallbackArg {
+    Event(Box<ForegroundEvent>),
+    // A `delta` mode event.
+    Delta(serde_json::Value),
+    Bytes(Vec<u8>),
+}
+
+// The JavaScript functions a listener reports to, and the NDJSON sink.
+pub struct Callbacks {
+    event: JsCallback,
+    on_close: Option<JsCallback>,
+    // Told about failures that don't throw, since they happen on the task.
+    on_error: Option<JsCallback>,
+    ndjson: Option<NdjsonWriter>,
+    // Settled once the hooks are installed.
+    ready: Ready,
+}
+
+// What a task tells once its hooks are installed, or have failed to be.
+enum Ready {
+    // The promise of a single start call, see `start_with`.
+    Promise(Deferred),
+    // One config of a `listenerStartMany` call.
+    Group(Arc<StartGroup>),
+}
+
+// The promise `listenerStartMany` returns. It resolves with the ids once every
+// config's hooks are installed, or rejects with the first failure, in which
+// case the configs that did install stop again. Either way each task waits
+// for the outcome before handling its first event.
+struct StartGroup {
+    state: Mutex<StartGroupState>,
+    // `Some(true)` once all installed, `Some(false)` once one failed.
+    outcome: watch::Sender<Option<bool>>,
+}
+
+struct StartGroupState {
+    size: usize,
+    remaining: usize,
+    // Attached once every config is valid, before any task starts, and taken
+    // by whichever settles it.
+    deferred: Option<Deferred>,
+    channel: Channel,
+}
+
+impl StartGroup {
+    fn new(size: usize, channel: Channel) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(StartGroupState {
+                size,
+                remaining: size,
+                deferred: None,
+                channel,
+            }),
+            outcome: watch::channel(None).0,
+        })
+    }
+
+    fn attach(&self, deferred: Deferred) {
+        let mut state = self.state.lock().unwrap();
+        if state.size == 0 {
+            // No task will ever report in.
+            deferred.settle_with(&state.channel, |mut cx| Ok(cx.empty_array()));
+            self.outcome.send_replace(Some(true));
+        } else {
+            state.deferred = Some(deferred);
+        }
+    }
+
+    fn fail(&self, err: Error) {
+        let mut state = self.state.lock().unwrap();
+        state.remaining -= 1;
+        if let Some(deferred) = state.deferred.take() {
+            deferred.settle_with(&state.channel, move |mut cx| -> JsResult<JsUndefined> {
+                err.throw(&mut cx)
+            });
+            self.outcome.send_replace(Some(false));
+        }
+    }
+
+    // Whether every config's hooks were installed, once that is known.
+    async fn installed(&self) -> bool {
+        let mut outcome = self.outcome.subscribe();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.remaining -= 1;
+            if state.remaining == 0 {
+                if let Some(deferred) = state.deferred.take() {
+                    let size = state.size;
+                    deferred.settle_with(&state.channel, move |mut cx| -> JsResult<JsArray> {
+                        let ids = cx.empty_array();
+                        for i in 0..size {
+                            let id = cx.number(i as f64);
+                            ids.set(&mut cx, i as u32, id)?;
+                        }
+                        Ok(ids)
+                    });
+                    self.outcome.send_replace(Some(true));
+                }
+            }
+        }
+
+        loop {
+            let known = *outcome.borrow();
+            if let Some(all_installed) = known {
+                return all_installed;
+            }
+            if outcome.changed().await.is_err() {
+                return false;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CloseReason {
+    // Stopped by `stop()` or by the callback.
+    Stopped,
+    MaxEvents,
+    HookFailed,
+    // The task was dropped without finishing, e.g. by the runtime shutting
+    // down.
+    RuntimeShutdown,
+    Panicked,
+}
+
+// Calls `onClose` exactly once when the listener task ends, however it ends.
+// If the task is dropped before firing explicitly, `Drop` reports why.
+struct CloseNotifier {
+    on_close: Option<JsCallback>,
+}
+
+impl CloseNotifier {
+    fn new(on_close: Option<JsCallback>) -> Self {
+        Self { on_close }
+    }
+
+    fn fire(&mut self, reason: CloseReason) {
+        if let Some(on_close) = self.on_close.take() {
+            on_close.notify(reason);
+        }
+    }
+}
+
+impl Drop for CloseNotifier {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.fire(CloseReason::Panicked);
+        } else {
+            self.fire(CloseReason::RuntimeShutdown);
+        }
+    }
+}
+
+// What the JavaScript callback asked the listener to do after handling an event.
+// Returning `false` or `{ stop: true }` from the callback stops the listener;
+// any other value keeps it running.
+#[derive(Debug, PartialEq)]
+pub enum CallbackControl {
+    Continue,
+    Stop,
+}
+
+impl CallbackControl {
+    fn from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<'a, JsValue>) -> NeonResult<Self> {
+        if let Ok(value) = value.downcast::<JsBoolean, _>(cx) {
+            if !value.value(cx) {
+                return Ok(CallbackControl::Stop);
+            }
+        } else if let Ok(value) = value.downcast::<JsObject, _>(cx) {
+            let stop = value.get_opt::<JsBoolean, _, _>(cx, "stop")?;
+            if let Some(stop) = stop {
+                if stop.value(cx) {
+                    return Ok(CallbackControl::Stop);
+                }
+            }
+        }
+
+        Ok(CallbackControl::Continue)
+    }
+}
 
 // https://github.com/neon-bindings/neon/issues/848
 // https://github.dev/owenthereal/neon-tonic-example/blob/master/src/lib.rs
 pub struct JsCallback {
     channel: Channel,
     callback: Arc<Root<JsFunction>>,
+    // The receiver for `callback`, e.g. the port for `postMessage`.
+    this: Option<Arc<Root<JsObject>>>,
 }
 
 impl JsCallback {
-    pub async fn call(
-        &self,
-        name: String,
-    ) -> Result<String, tokio::sync::oneshot::error::RecvError> {
-        let (tx, rx) = tokio::sync::oneshot::channel();
+    // Read an optional function-valued property from the options argument.
+    fn from_option<'a, C: Context<'a>>(
+        cx: &mut C,
+        options: Option<Handle<'a, JsValue>>,
+        key: &str,
+    ) -> NeonResult<Option<Self>> {
+        let options = match options {
+            Some(options) => match options.downcast::<JsObject, _>(cx) {
+                Ok(options) => options,
+                Err(_) => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        let callback = match options.get_opt::<JsFunction, _, _>(cx, key)? {
+            Some(callback) => callback,
+            None => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            channel: cx.channel(),
+            callback: Arc::new(callback.root(cx)),
+            this: None,
+        }))
+    }
+
+    // Call with a single serialized argument without waiting for the result.
+    pub fn notify<T: serde::Serialize + Send + 'static>(&self, arg: T) {
         let callback = self.callback.clone();
         let _ = self.channel.try_send(move |mut cx| {
             let this = cx.undefined();
-            let arg = cx.string(name);
+            let arg = value::to_js(&mut cx, &arg)?;
+
+            callback.to_inner(&mut cx).call(&mut cx, this, vec![arg])?;
+
+            Ok(())
+        });
+    }
+
+    // Like `call`, but retry up to `delivery.retries` times with a doubling
+    // delay when the event couldn't be queued. An event whose callback ran and
+    // threw isn't retried, so the callback never sees an event twice. Stopping
+    // waits for the retries in progress.
+    pub async fn deliver(
+        &self,
+        payload: ForegroundEvent,
+        delivery: &mut Delivery,
+    ) -> Result<CallbackControl, CallError> {
+        let delta = match &mut delivery.delta {
+            Some(encoder) => match encoder.encode(&payload) {
+                Ok(delta) => Some(delta),
+                Err(err) => return Err(CallError::Encode(err.to_string())),
+            },
+            None => None,
+        };
+        let arg = match (delivery.format, delta) {
+            (PayloadFormat::Object, None) => CallbackArg::Event(Box::new(payload)),
+            (PayloadFormat::Object, Some(delta)) => CallbackArg::Delta(delta),
+            (PayloadFormat::MessagePack, delta) => {
+                let bytes = match &delta {
+                    Some(delta) => value::to_msgpack(delta),
+                    None => value::to_msgpack(&payload),
+                };
+                match bytes {
+                    Ok(bytes) => CallbackArg::Bytes(bytes),
+                    Err(err) => return Err(CallError::Encode(err.to_string())),
+                }
+            }
+        };
+
+        let result = self.call_with_retries(arg, delivery).await;
+        // The consumer can't merge the next delta onto an event it may not
+        // have seen.
+        if let (Err(_), Some(encoder)) = (&result, &mut delivery.delta) {
+            encoder.resync();
+        }
+
+        result
+    }
+
+    async fn call_with_retries(
+        &self,
+        arg: CallbackArg,
+        delivery: &Delivery,
+    ) -> Result<CallbackControl, CallError> {
+        let mut delay = delivery.retry_delay;
+        for _ in 0..delivery.retries {
+            match self.call(arg.clone(), delivery).await {
+                Err(CallError::NotQueued) => {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+
+        self.call(arg, delivery).await
+    }
+
+    // Wait at most `delivery.timeout` for the callback to return. A callback
+    // that times out may still run later, when the main thread catches up,
+    // unless the listener has been stopped by then.
+    async fn call(
+        &self,
+        arg: CallbackArg,
+        delivery: &Delivery,
+    ) -> Result<CallbackControl, CallError> {
+        // The queued closure owns its own clone of the callback root and the
+        // sender, so it stays valid even if this future is dropped before it
+        // runs; `tx.send` then simply fails and the root is released normally.
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let callback = self.callback.clone();
+        let this = self.this.clone();
+        // Set once the closure runs, to tell a callback that threw apart from
+        // one that was never called.
+        let ran = Arc::new(AtomicBool::new(false));
+        let closure_ran = ran.clone();
+        let stopped = delivery.stopped.clone();
+        let queued = self.channel.try_send(move |mut cx| {
+            if stopped.load(Ordering::SeqCst) {
+                let _ = tx.send(CallbackControl::Stop);
+                return Ok(());
+            }
+            closure_ran.store(true, Ordering::SeqCst);
+            let this: Handle<JsValue> = match &this {
+                Some(this) => this.to_inner(&mut cx).upcast(),
+                None => cx.undefined().upcast(),
+            };
+            let arg = match &arg {
+                CallbackArg::Event(payload) => payload.to_js(&mut cx)?,
+                CallbackArg::Delta(delta) => delta::to_js(&mut cx, delta)?,
+                CallbackArg::Bytes(bytes) => {
+                    let mut buffer = JsBuffer::new(&mut cx, bytes.len())?;
+                    buffer.as_mut_slice(&mut cx).copy_from_slice(bytes);
+                    buffer.upcast()
+                }
+            };
 
             let value = callback
                 .to_inner(&mut cx)
-                .call(&mut cx, this, vec![arg.upcast()])?
-                .downcast_or_throw::<JsString, _>(&mut cx)?
-                .value(&mut cx);
+                .call(&mut cx, this, vec![arg])?;
+            let control = CallbackControl::from_js(&mut cx, value)?;
 
-            let _ = tx.send(value);
+            let _ = tx.send(control);
 
             Ok(())
         });
 
-        rx.await
+        if queued.is_err() {
+            return Err(CallError::NotQueued);
+        }
+
+        let result = match delivery.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, rx).await {
+                Ok(result) => result,
+                Err(_) => return Err(CallError::TimedOut),
+            },
+            None => rx.await,
+        };
+        result.map_err(|_| {
+            if ran.load(Ordering::SeqCst) {
+                CallError::Threw
+            } else {
+                CallError::NotQueued
+            }
+        })
         // rx.await?
         // rx.await
         //     .map_err(|err: tokio::sync::oneshot::error::RecvError| format!("Failed to call JavaScript: {:?}", err)).
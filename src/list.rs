@@ -0,0 +1,140 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::time::Instant;
+use winapi::shared::windef::HWND;
+
+use crate::event::Rect;
+use crate::{handle, monitor, window};
+
+// One entry of `listWindows()`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowInfo {
+    // A decimal string, like the event `hwnd`.
+    pub hwnd: String,
+    pub title: Option<String>,
+    pub pid: Option<u32>,
+    pub rect: Option<Rect>,
+    // The monitor the window's center is on, in the stable monitor order.
+    pub monitor_index: Option<usize>,
+}
+
+// Visible top-level windows, front to back. With `monitor_index`, only those
+// whose center is on that monitor, which is nothing for an index past the
+// last monitor.
+pub fn list_windows(monitor_index: Option<usize>) -> Vec<WindowInfo> {
+    let monitors = monitor::enumerate_monitors();
+
+    window::enum_windows()
+        .into_iter()
+        .filter(|&window| window::is_visible(window))
+        .map(|window| window_info(window, &monitors))
+        .filter(|info| monitor_index.is_none_or(|index| info.monitor_index == Some(index)))
+        .collect()
+}
+
+fn window_info(window: HWND, monitors: &[monitor::MonitorInfo]) -> WindowInfo {
+    let rect = window::get_window_rect(window).ok().map(Rect::from);
+    let monitor_index = rect.and_then(|rect| {
+        let x = rect.left + (rect.right - rect.left) / 2;
+        let y = rect.top + (rect.bottom - rect.top) / 2;
+        monitors.iter().position(|monitor| {
+            let bounds = monitor.rect;
+            x >= bounds.left && x < bounds.right && y >= bounds.top && y < bounds.bottom
+        })
+    });
+
+    WindowInfo {
+        hwnd: handle::to_string(handle::from_hwnd(window)),
+        title: window::read_window_text(window).ok().flatten(),
+        pid: window::get_window_process_id(window).ok(),
+        rect,
+        monitor_index,
+    }
+}
+
+// Top-level windows whose title is exactly `title`, front to back, so the
+// first is the topmost. Hidden windows are included, since the caller asked
+// for the window by name.
+pub fn find_by_title(title: &str) -> Vec<HWND> {
+    window::enum_windows()
+        .into_iter()
+        .filter(|&window| window::read_window_text(window).ok().flatten().as_deref() == Some(title))
+        .collect()
+}
+
+// A top-level window that appeared or went away between two polls. Handles
+// are kept as plain values, since the poller lives across awaits.
+pub enum WindowChange {
+    Opened(isize),
+    Closed(isize),
+}
+
+// Polls the visible top-level windows every `interval` for
+// `windowListIntervalMs`, and diffs each snapshot against the last. A window
+// that's hidden and shown again counts as closed and reopened, and one that
+// opens and closes between two polls isn't seen at all.
+pub struct WindowListPoller {
+    interval: Duration,
+    next_poll: Instant,
+    // `None` until the first poll, which only records the baseline.
+    known: Option<HashSet<isize>>,
+    queued: VecDeque<WindowChange>,
+}
+
+impl WindowListPoller {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_poll: Instant::now(),
+            known: None,
+            queued: VecDeque::new(),
+        }
+    }
+
+    // Now while changes from the last poll are still queued.
+    pub fn deadline(&self) -> Instant {
+        if self.queued.is_empty() {
+            self.next_poll
+        } else {
+            Instant::now()
+        }
+    }
+
+    // The next queued change, polling first if none is queued.
+    pub fn next_change(&mut self) -> Option<WindowChange> {
+        if self.queued.is_empty() {
+            self.poll();
+        }
+        self.queued.pop_front()
+    }
+
+    fn poll(&mut self) {
+        self.next_poll = Instant::now() + self.interval;
+
+        let windows: Vec<HWND> = window::enum_windows()
+            .into_iter()
+            .filter(|&window| window::is_visible(window))
+            .collect();
+        let current: HashSet<isize> = windows
+            .iter()
+            .map(|&window| handle::from_hwnd(window))
+            .collect();
+
+        if let Some(known) = &self.known {
+            for &window in &windows {
+                let hwnd = handle::from_hwnd(window);
+                if !known.contains(&hwnd) {
+                    self.queued.push_back(WindowChange::Opened(hwnd));
+                }
+            }
+            for &hwnd in known.difference(&current) {
+                self.queued.push_back(WindowChange::Closed(hwnd));
+            }
+        }
+
+        self.known = Some(current);
+    }
+}
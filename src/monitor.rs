@@ -0,0 +1,141 @@
+use std::{mem, ptr};
+
+use winapi::{
+    shared::{
+        minwindef::{BOOL, LPARAM, TRUE},
+        windef::{HDC, HMONITOR, HWND, LPRECT},
+    },
+    um::winuser::{
+        EnumDisplayMonitors, GetMonitorInfoW, GetSystemMetrics, MonitorFromWindow,
+        MONITORINFO, MONITORINFOF_PRIMARY, MONITOR_DEFAULTTONEAREST, SM_CMONITORS,
+    },
+};
+
+use crate::event::Rect;
+use crate::window;
+
+#[derive(Clone)]
+pub struct MonitorInfo {
+    // Kept as an integer so the list can be held across awaits.
+    pub handle: isize,
+    pub rect: Rect,
+    pub work_area: Rect,
+    pub is_primary: bool,
+}
+
+// All display monitors in a stable order: the primary monitor first, then the
+// rest left to right and top to bottom. `EnumDisplayMonitors` itself makes no
+// ordering guarantee, so indexes are only meaningful in this order.
+pub fn enumerate_monitors() -> Vec<MonitorInfo> {
+    unsafe extern "system" fn callback(
+        monitor: HMONITOR,
+        _hdc: HDC,
+        _rect: LPRECT,
+        data: LPARAM,
+    ) -> BOOL {
+        let monitors = &mut *(data as *mut Vec<MonitorInfo>);
+        if let Some(info) = get_monitor_info(monitor) {
+            monitors.push(info);
+        }
+        TRUE
+    }
+
+    let mut monitors: Vec<MonitorInfo> = Vec::new();
+    unsafe {
+        EnumDisplayMonitors(
+            ptr::null_mut(),
+            ptr::null(),
+            Some(callback),
+            &mut monitors as *mut Vec<MonitorInfo> as LPARAM,
+        )
+    };
+
+    monitors.sort_by_key(|monitor| (!monitor.is_primary, monitor.rect.left, monitor.rect.top));
+    monitors
+}
+
+fn get_monitor_info(monitor: HMONITOR) -> Option<MonitorInfo> {
+    let mut info: MONITORINFO = unsafe { mem::zeroed() };
+    info.cbSize = mem::size_of::<MONITORINFO>() as u32;
+    if unsafe { GetMonitorInfoW(monitor, &mut info) } == 0 {
+        return None;
+    }
+
+    Some(MonitorInfo {
+        handle: monitor as isize,
+        rect: info.rcMonitor.into(),
+        work_area: info.rcWork.into(),
+        is_primary: info.dwFlags & MONITORINFOF_PRIMARY != 0,
+    })
+}
+
+// Whether the window covers its whole monitor, taskbar included. The desktop
+// covers every monitor too, but isn't fullscreen in any useful sense.
+pub fn is_fullscreen(window: HWND) -> bool {
+    let rect = match window::get_window_rect(window) {
+        Ok(rect) => rect,
+        Err(_) => return false,
+    };
+    let monitor = unsafe { MonitorFromWindow(window, MONITOR_DEFAULTTONEAREST) };
+    let monitor = match get_monitor_info(monitor) {
+        Some(monitor) => monitor.rect,
+        None => return false,
+    };
+
+    let covers = rect.left <= monitor.left
+        && rect.top <= monitor.top
+        && rect.right >= monitor.right
+        && rect.bottom >= monitor.bottom;
+    covers
+        && !matches!(
+            window::get_class_name(window).as_deref(),
+            Ok("Progman") | Ok("WorkerW")
+        )
+}
+
+// A cached monitor list, re-enumerated when the display configuration
+// changes. Hot-plugging a monitor gives it a new `HMONITOR` and changes the
+// monitor count, so either is treated as a configuration change.
+pub struct Monitors {
+    monitors: Vec<MonitorInfo>,
+}
+
+impl Monitors {
+    pub fn new() -> Self {
+        Self {
+            monitors: enumerate_monitors(),
+        }
+    }
+
+    // The monitor the window is mostly on, as an index into the stable order.
+    pub fn index_of(&mut self, window: HWND) -> Option<usize> {
+        Some(self.lookup(window)?.0)
+    }
+
+    // The monitor the window is mostly on and its index. The info itself is
+    // re-read, since the work area changes without a configuration change
+    // when the taskbar is moved or resized.
+    pub fn lookup(&mut self, window: HWND) -> Option<(usize, MonitorInfo)> {
+        let monitor = unsafe { MonitorFromWindow(window, MONITOR_DEFAULTTONEAREST) };
+        let handle = monitor as isize;
+        if handle == 0 {
+            return None;
+        }
+
+        let count = unsafe { GetSystemMetrics(SM_CMONITORS) } as usize;
+        if count != self.monitors.len() || !self.contains(handle) {
+            self.monitors = enumerate_monitors();
+        }
+
+        let index = self
+            .monitors
+            .iter()
+            .position(|monitor| monitor.handle == handle)?;
+        let info = get_monitor_info(monitor).unwrap_or_else(|| self.monitors[index].clone());
+        Some((index, info))
+    }
+
+    fn contains(&self, handle: isize) -> bool {
+        self.monitors.iter().any(|monitor| monitor.handle == handle)
+    }
+}
@@ -0,0 +1,45 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::event::ForegroundEvent;
+
+// Writes each event as one line of JSON to a file or named pipe, so logging
+// consumers don't need a JavaScript callback at all.
+pub struct NdjsonWriter {
+    writer: BufWriter<File>,
+}
+
+impl NdjsonWriter {
+    // Files are appended to, so restarting a tracker keeps earlier lines. A
+    // pipe must already exist: it is opened as a client, and opening fails if
+    // no server is listening.
+    pub fn open(path: &Path) -> Result<Self> {
+        let is_pipe = path
+            .to_str()
+            .is_some_and(|path| path.starts_with(r"\\.\pipe\"));
+        let file = if is_pipe {
+            OpenOptions::new().write(true).open(path)
+        } else {
+            OpenOptions::new().create(true).append(true).open(path)
+        }
+        .map_err(|err| Error::NdjsonOpen(path.to_owned(), err))?;
+
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, event: &ForegroundEvent) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, event)
+            .map_err(|err| Error::NdjsonWrite(err.into()))?;
+        self.writer
+            .write_all(b"\n")
+            .map_err(Error::NdjsonWrite)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush().map_err(Error::NdjsonWrite)
+    }
+}
@@ -0,0 +1,728 @@
+use neon::prelude::*;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::expr::Expression;
+use crate::{handle, ordered, priority, window};
+
+const MIN_WINDOW_LIST_INTERVAL: Duration = Duration::from_millis(50);
+
+// Generous, since the callback runs user code on a possibly busy thread.
+const DEFAULT_CALLBACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+// The first `callbackRetryMs` backoff if none is given.
+const DEFAULT_CALLBACK_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+// Options accepted by `listenerStart` as an optional third argument.
+#[derive(Clone, Debug, Default)]
+pub struct StartOptions {
+    // Skip every optional field group and deliver only the window handle.
+    pub minimal: bool,
+    // Emit `idle`/`active` events when there has been no user input for this
+    // long, and when input resumes.
+    pub idle_threshold: Option<Duration>,
+    // Include a best-effort `isPinned` flag for taskbar-pinned apps.
+    pub include_pinned: bool,
+    // Include the `virtualDesktopId` of the window's virtual desktop.
+    pub include_virtual_desktop: bool,
+    // Include the active tab's `browserUrl` for supported browsers. Slow: a
+    // UI Automation query per event.
+    pub include_browser_url: bool,
+    // Include the `ancestry` chain of owner and parent windows.
+    pub include_ancestry: bool,
+    // Include a best-effort `isModal` flag for modal dialogs.
+    pub include_modal: bool,
+    // Include a best-effort `taskbarIndex`. Walks the taskbar through UI
+    // Automation, which is slow, so the buttons are cached for a few seconds.
+    pub include_taskbar_index: bool,
+    // Drop every event while a window of our own process is in the
+    // foreground, including idle and tracking events.
+    pub only_when_self_background: bool,
+    // Only deliver foreground events for windows the taskbar would show,
+    // dropping transient and helper windows.
+    pub app_windows_only: bool,
+    // Only deliver foreground events for windows Alt+Tab would list, see
+    // `window::is_alt_tab_eligible`. Stricter than `app_windows_only` about
+    // owned windows and cloaked ones.
+    pub alt_tab_eligible_only: bool,
+    // Only deliver foreground events for windows on this monitor, using the
+    // same index as the `monitorIndex` field.
+    pub monitor_index: Option<usize>,
+    // Only deliver foreground events for this window and the windows it owns
+    // or parents, directly or through other windows.
+    pub owner_hwnd: Option<isize>,
+    // Drop foreground changes to windows narrower or shorter than this many
+    // pixels, usually tooltips, menus and IME candidate windows.
+    pub min_width: Option<usize>,
+    pub min_height: Option<usize>,
+    // Only hook events raised by this thread, e.g. one of our own UI threads.
+    // Hooks stay out of context.
+    pub hook_thread_id: Option<NonZeroU32>,
+    // Drop foreground changes to windows whose title is empty or whitespace.
+    pub require_title: bool,
+    // Include a heuristic `cause` for foreground changes: "keyboard",
+    // "mouse", "programmatic" or "unknown".
+    pub include_cause: bool,
+    // Report foreground changes as `focusStolen` when the window activated
+    // itself and there has been no user input for this long.
+    pub focus_stolen_threshold: Option<Duration>,
+    // Deliver the first foreground event of a burst at once and the last one
+    // after no new event has arrived for this long.
+    pub throttle: Option<Duration>,
+    // Also poll the visible top-level windows this often and emit
+    // `windowOpened` and `windowClosed` for the differences, for every
+    // process regardless of `pid`.
+    pub window_list_interval: Option<Duration>,
+    // Deliver every foreground change at once with `settled: false`, and again
+    // with `settled: true` once no other change has followed for this long.
+    pub settle: Option<Duration>,
+    // Deliver only the last of a burst of foreground changes, or of a tracked
+    // window's title changes, once that kind of event has been quiet for
+    // this long. Each kind has its own timer.
+    pub foreground_debounce: Option<Duration>,
+    pub name_change_debounce: Option<Duration>,
+    // Always include `rawHwnd` (a BigInt) and `pid`, for companion native
+    // addons.
+    pub raw_handles: bool,
+    // Attach the WinEvent's own fields as `rawEvent`, for diagnosing
+    // unexpected event patterns.
+    pub raw_event: bool,
+    // How many recent foreground events `getHistory()` keeps. 0 disables it.
+    pub history_size: usize,
+    // Windows whose foreground events are dropped, e.g. the consumer's own
+    // overlays. Can be replaced at runtime with `setIgnoredHwnds`. `None` if
+    // not given, so reconfiguring without it keeps the current set.
+    pub ignore_hwnds: Option<HashSet<isize>>,
+    // Include `stableId`, which unlike `hwnd` isn't reused by later windows
+    // of other processes.
+    pub include_stable_id: bool,
+    // Include `processStartTime` and `processAgeMs`.
+    pub include_process_times: bool,
+    // Include `workingSetBytes` and `cpuTimeMs` for the owning process.
+    pub include_resource_usage: bool,
+    // Include `accessibleName`, read through UI Automation. Each read can
+    // take up to `uia::TIMEOUT`.
+    pub include_accessible_name: bool,
+    // Include the window `title`.
+    pub include_title: bool,
+    // Also split the title into `documentTitle` and `appTitle` around the
+    // last occurrence of this separator. Implies `include_title`.
+    pub title_separator: Option<String>,
+    // Clean up `title` with `event::sanitize_title`, keeping the title as
+    // read in `rawTitle` when that changed it. Implies `include_title`.
+    pub sanitize_title: bool,
+    // Include `from` and `to` with the previous and new foreground windows'
+    // rects.
+    pub include_transition: bool,
+    // Include `isLayered` and `opacity`.
+    pub include_composition: bool,
+    // Include `restoredRect` and `showState`.
+    pub include_restored_rect: bool,
+    // Include `clipboardChanged` with foreground changes.
+    pub include_clipboard_change: bool,
+    // Include `previousExitReason` with foreground changes.
+    pub include_previous_exit_reason: bool,
+    // Include the owning process's `exePath`.
+    pub include_exe_path: bool,
+    // Include the owning process's `commandLine`. See
+    // `process::get_process_command_line` for why it is often `null`.
+    pub include_command_line: bool,
+    // Include the owning process's `parentPid` and `parentExe`. Costs a
+    // process query per new PID.
+    pub include_parent_process: bool,
+    // Resolve device paths and short names in `exePath`, keeping the original
+    // in `rawExePath`.
+    pub canonicalize_exe_path: bool,
+    // Only deliver foreground events for windows that carry this window
+    // property, optionally with a specific value.
+    pub window_property: Option<WindowProperty>,
+    // Only deliver foreground events for windows this `filterExpression`
+    // matches, see `expr` for the syntax.
+    pub filter_expression: Option<Expression>,
+    // Also emit `sessionChange` events as the session is locked, unlocked,
+    // logged on or off, or connected and disconnected.
+    pub session_events: bool,
+    // Also emit `fullscreenEnter`/`fullscreenExit` as the foreground window
+    // starts or stops covering its monitor. Adds a system-wide location
+    // change hook.
+    pub fullscreen_events: bool,
+    // Follow this one window instead of listening to foreground changes.
+    // Set by `listenerTrackWindow` rather than parsed from the options.
+    pub track_hwnd: Option<isize>,
+    // Suppress foreground events that repeat the last delivered window or
+    // process.
+    pub dedupe_by: Option<DedupeKey>,
+    // Only suppress a repeat seen within this long of the previous one, so
+    // switching back after a while still fires. Implies `dedupeBy: "hwnd"`
+    // if `dedupeBy` isn't given.
+    pub dedupe_window: Option<Duration>,
+    // Deliver every foreground event, annotated with which filters it passed,
+    // instead of dropping the ones that fail.
+    pub debug_filters: bool,
+    // Report `processingLatencyUs` on events built from a WinEvent.
+    pub debug_latency: bool,
+    // Tally foreground time per process or executable for
+    // `getUsageStats()`.
+    pub usage_stats_by: Option<UsageKey>,
+    // Deliver nothing until a window of this executable comes to the
+    // foreground, given as a file name or a full path, case-insensitively.
+    pub wait_for_exe: Option<String>,
+    // Whether the foreground event that ends the wait is delivered.
+    pub include_waited_event: bool,
+    // Only deliver the foreground leaving this process or executable for
+    // another process, see `Pipeline::away_from`.
+    pub away_from: Option<AwayFrom>,
+    // Drop window events for this long after the hooks are installed.
+    pub startup_quiet: Option<Duration>,
+    // Stop on its own after delivering this many events.
+    pub max_events: Option<usize>,
+    // Also write every event as a line of JSON to this file or named pipe.
+    pub ndjson_path: Option<PathBuf>,
+    // Only write to `ndjsonPath` and never call the callback.
+    pub ndjson_only: bool,
+    // Retry an event this many times, doubling `callback_retry_delay` each
+    // time, when it couldn't be queued to the main thread.
+    pub callback_retries: u32,
+    pub callback_retry_delay: Duration,
+    // Process events at this `SetThreadPriority` level, one of
+    // `priority::LEVELS`.
+    pub thread_priority: Option<i32>,
+    // How events are handed to the callback.
+    pub payload_format: PayloadFormat,
+    // Only hand the callback the fields that changed since the previous
+    // event, see `delta::DeltaEncoder`.
+    pub delta: bool,
+    // Deliver WinEvents in exactly the order they arrived, through a buffer
+    // of this many, see `ordered::EventSource`. Can't be combined with the
+    // options that hold events back, nor with `delta`, which numbers events
+    // by its own `seq`.
+    pub strict_order: Option<usize>,
+    // Give up waiting for the callback to return after this long, so a
+    // wedged main thread can't stall the task forever. `None` waits forever.
+    pub callback_timeout: Option<Duration>,
+    // Tags every event with `listenerId`. Set by `listenerStartMany` to the
+    // config's index rather than parsed from the options.
+    pub listener_id: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AwayFrom {
+    Pid(u32),
+    // A file name or full path, lowercased, matched like `waitForExe`.
+    Exe(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DedupeKey {
+    Hwnd,
+    // Switching between windows of the same process is suppressed, which is
+    // the right granularity for app-usage tracking.
+    Pid,
+    // Only switching to a different executable fires, even across separate
+    // processes of the same executable (e.g. browser windows).
+    Exe,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UsageKey {
+    // Keyed by the decimal PID.
+    Pid,
+    // Keyed by the executable's path, falling back to the PID when it can't
+    // be read.
+    Exe,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PayloadFormat {
+    // A plain object.
+    #[default]
+    Object,
+    // A `Buffer` of MessagePack, encoded before the event reaches the main
+    // thread. The map has the same keys as the object.
+    MessagePack,
+}
+
+#[derive(Clone, Debug)]
+pub struct WindowProperty {
+    // Null-terminated UTF-16, ready to pass to `GetPropW`.
+    pub key: Vec<u16>,
+    pub value: Option<isize>,
+}
+
+// Every key `StartOptions::from_js` reads, plus `onClose`, `onError` and
+// `multipleMatches`, which the start functions read themselves.
+const OPTION_KEYS: &[&str] = &[
+    "minimal",
+    "idleThresholdMs",
+    "includePinned",
+    "includeTaskbarIndex",
+    "includeModal",
+    "includeAncestry",
+    "includeBrowserUrl",
+    "includeVirtualDesktop",
+    "appWindowsOnly",
+    "altTabEligibleOnly",
+    "onlyWhenSelfBackground",
+    "monitorIndex",
+    "ownerHwnd",
+    "hookThreadId",
+    "inContext",
+    "minWidth",
+    "minHeight",
+    "requireTitle",
+    "throttleMs",
+    "focusStolenThresholdMs",
+    "includeCause",
+    "settleMs",
+    "windowListIntervalMs",
+    "foregroundDebounceMs",
+    "nameChangeDebounceMs",
+    "rawHandles",
+    "rawEvent",
+    "historySize",
+    "ignoreHwnds",
+    "includeStableId",
+    "includeProcessTimes",
+    "includeResourceUsage",
+    "includeAccessibleName",
+    "includeComposition",
+    "includeRestoredRect",
+    "includeClipboardChange",
+    "includePreviousExitReason",
+    "includeTransition",
+    "includeTitle",
+    "splitTitle",
+    "sanitizeTitle",
+    "includeExePath",
+    "canonicalizeExePath",
+    "includeCommandLine",
+    "includeParentProcess",
+    "windowProperty",
+    "filterExpression",
+    "dedupeBy",
+    "dedupeWindowMs",
+    "debugFilters",
+    "debugLatency",
+    "usageStatsBy",
+    "sessionEvents",
+    "fullscreenEvents",
+    "waitForExe",
+    "includeWaitedEvent",
+    "awayFromPid",
+    "awayFromExe",
+    "startupQuietMs",
+    "maxEvents",
+    "ndjsonPath",
+    "ndjsonOnly",
+    "callbackRetries",
+    "callbackRetryMs",
+    "callbackTimeoutMs",
+    "payloadFormat",
+    "delta",
+    "strictOrder",
+    "strictOrderBufferSize",
+    "threadPriority",
+    "onClose",
+    "onError",
+    "multipleMatches",
+];
+
+// Emit a warning for each own key of `obj` that isn't an option or one of
+// `extra`, most likely a typo.
+pub fn warn_unknown_keys<'a, C: Context<'a>>(
+    cx: &mut C,
+    obj: Handle<'a, JsObject>,
+    extra: &[&str],
+) -> NeonResult<()> {
+    let keys = obj.get_own_property_names(cx)?.to_vec(cx)?;
+    for key in keys {
+        let key = match key.downcast::<JsString, _>(cx) {
+            Ok(key) => key.value(cx),
+            Err(_) => continue,
+        };
+        if !OPTION_KEYS.contains(&key.as_str()) && !extra.contains(&key.as_str()) {
+            emit_warning(cx, &format!("Unknown option `{}`", key))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn emit_warning<'a, C: Context<'a>>(cx: &mut C, message: &str) -> NeonResult<()> {
+    let process = match cx.global().get_opt::<JsObject, _, _>(cx, "process")? {
+        Some(process) => process,
+        None => return Ok(()),
+    };
+    if let Some(emit_warning) = process.get_opt::<JsFunction, _, _>(cx, "emitWarning")? {
+        let message = cx.string(message);
+        emit_warning.call(cx, process, vec![message.upcast()])?;
+    }
+
+    Ok(())
+}
+
+impl StartOptions {
+    pub fn from_js<'a, C: Context<'a>>(
+        cx: &mut C,
+        value: Option<Handle<'a, JsValue>>,
+    ) -> NeonResult<Self> {
+        let mut options = StartOptions::default();
+
+        let obj = match value {
+            Some(value) if !value.is_a::<JsUndefined, _>(cx) && !value.is_a::<JsNull, _>(cx) => {
+                match value.downcast::<JsObject, _>(cx) {
+                    Ok(obj) => obj,
+                    Err(_) => return cx.throw_type_error("`options` (argument 2) must be an object"),
+                }
+            }
+            _ => return Ok(options),
+        };
+
+        options.minimal = get_bool(cx, obj, "minimal")?.unwrap_or(false);
+        options.idle_threshold = get_duration(cx, obj, "idleThresholdMs")?;
+        options.include_pinned = get_bool(cx, obj, "includePinned")?.unwrap_or(false);
+        options.include_virtual_desktop =
+            get_bool(cx, obj, "includeVirtualDesktop")?.unwrap_or(false);
+        options.include_browser_url = get_bool(cx, obj, "includeBrowserUrl")?.unwrap_or(false);
+        options.include_ancestry = get_bool(cx, obj, "includeAncestry")?.unwrap_or(false);
+        options.include_cause = get_bool(cx, obj, "includeCause")?.unwrap_or(false);
+        options.include_modal = get_bool(cx, obj, "includeModal")?.unwrap_or(false);
+        options.include_taskbar_index =
+            get_bool(cx, obj, "includeTaskbarIndex")?.unwrap_or(false);
+        options.app_windows_only = get_bool(cx, obj, "appWindowsOnly")?.unwrap_or(false);
+        options.alt_tab_eligible_only = get_bool(cx, obj, "altTabEligibleOnly")?.unwrap_or(false);
+        options.only_when_self_background =
+            get_bool(cx, obj, "onlyWhenSelfBackground")?.unwrap_or(false);
+        options.monitor_index = get_index(cx, obj, "monitorIndex")?;
+        options.hook_thread_id = match get_index(cx, obj, "hookThreadId")? {
+            Some(id) if id > 0 && id <= u32::MAX as usize => NonZeroU32::new(id as u32),
+            Some(_) => return cx.throw_range_error("`hookThreadId` must be a thread id"),
+            None => None,
+        };
+        // In-context hooks run inside the process that raises the event,
+        // which `wineventhook` doesn't support: it always installs
+        // `WINEVENT_OUTOFCONTEXT` hooks routed through its own message loop.
+        // Refuse rather than silently hooking out of context.
+        // `hookThreadId` is the closest supported scoping.
+        if get_bool(cx, obj, "inContext")?.unwrap_or(false) {
+            return cx.throw_error(
+                "`inContext` hooks aren't supported; use `hookThreadId` to scope out-of-context hooks to a thread",
+            );
+        }
+        options.min_width = get_index(cx, obj, "minWidth")?;
+        options.min_height = get_index(cx, obj, "minHeight")?;
+        options.require_title = get_bool(cx, obj, "requireTitle")?.unwrap_or(false);
+        options.throttle = get_duration(cx, obj, "throttleMs")?;
+        options.settle = get_duration(cx, obj, "settleMs")?;
+        // Anything faster than `MIN_WINDOW_LIST_INTERVAL` would just burn CPU on
+        // enumeration.
+        options.window_list_interval = get_duration(cx, obj, "windowListIntervalMs")?
+            .map(|interval| interval.max(MIN_WINDOW_LIST_INTERVAL));
+        options.foreground_debounce = get_duration(cx, obj, "foregroundDebounceMs")?;
+        options.name_change_debounce = get_duration(cx, obj, "nameChangeDebounceMs")?;
+        options.focus_stolen_threshold = get_duration(cx, obj, "focusStolenThresholdMs")?;
+        options.raw_handles = get_bool(cx, obj, "rawHandles")?.unwrap_or(false);
+        options.raw_event = get_bool(cx, obj, "rawEvent")?.unwrap_or(false);
+        options.history_size = get_index(cx, obj, "historySize")?.unwrap_or(0);
+        options.include_stable_id = get_bool(cx, obj, "includeStableId")?.unwrap_or(false);
+        options.include_process_times =
+            get_bool(cx, obj, "includeProcessTimes")?.unwrap_or(false);
+        options.include_resource_usage =
+            get_bool(cx, obj, "includeResourceUsage")?.unwrap_or(false);
+        options.include_accessible_name =
+            get_bool(cx, obj, "includeAccessibleName")?.unwrap_or(false);
+        options.include_composition = get_bool(cx, obj, "includeComposition")?.unwrap_or(false);
+        options.include_restored_rect =
+            get_bool(cx, obj, "includeRestoredRect")?.unwrap_or(false);
+        options.include_clipboard_change =
+            get_bool(cx, obj, "includeClipboardChange")?.unwrap_or(false);
+        options.include_previous_exit_reason =
+            get_bool(cx, obj, "includePreviousExitReason")?.unwrap_or(false);
+        options.include_transition = get_bool(cx, obj, "includeTransition")?.unwrap_or(false);
+        options.include_title = get_bool(cx, obj, "includeTitle")?.unwrap_or(false);
+        // `true` for the default separator, or the separator itself.
+        if let Some(split) = obj.get_opt::<JsValue, _, _>(cx, "splitTitle")? {
+            if let Ok(split) = split.downcast::<JsBoolean, _>(cx) {
+                if split.value(cx) {
+                    options.title_separator = Some(" - ".to_string());
+                }
+            } else if let Ok(separator) = split.downcast::<JsString, _>(cx) {
+                let separator = separator.value(cx);
+                if separator.is_empty() {
+                    return cx.throw_type_error("`splitTitle` must not be an empty string");
+                }
+                options.title_separator = Some(separator);
+            } else {
+                return cx.throw_type_error("`splitTitle` must be a boolean or a string");
+            }
+            options.include_title |= options.title_separator.is_some();
+        }
+        options.sanitize_title = get_bool(cx, obj, "sanitizeTitle")?.unwrap_or(false);
+        options.include_title |= options.sanitize_title;
+        options.include_exe_path = get_bool(cx, obj, "includeExePath")?.unwrap_or(false);
+        options.canonicalize_exe_path =
+            get_bool(cx, obj, "canonicalizeExePath")?.unwrap_or(false);
+        options.include_command_line = get_bool(cx, obj, "includeCommandLine")?.unwrap_or(false);
+        options.include_parent_process =
+            get_bool(cx, obj, "includeParentProcess")?.unwrap_or(false);
+        options.session_events = get_bool(cx, obj, "sessionEvents")?.unwrap_or(false);
+        options.fullscreen_events = get_bool(cx, obj, "fullscreenEvents")?.unwrap_or(false);
+        options.wait_for_exe = get_string(cx, obj, "waitForExe")?.map(|exe| exe.to_lowercase());
+        options.include_waited_event = get_bool(cx, obj, "includeWaitedEvent")?.unwrap_or(true);
+        let away_from_pid = get_index(cx, obj, "awayFromPid")?;
+        let away_from_exe = get_string(cx, obj, "awayFromExe")?;
+        options.away_from = match (away_from_pid, away_from_exe) {
+            (Some(_), Some(_)) => {
+                return cx.throw_type_error("`awayFromPid` and `awayFromExe` can't be combined")
+            }
+            (Some(pid), None) => match u32::try_from(pid) {
+                Ok(pid) if pid != 0 => Some(AwayFrom::Pid(pid)),
+                _ => return cx.throw_range_error("`awayFromPid` must be a process id"),
+            },
+            (None, Some(exe)) => Some(AwayFrom::Exe(exe.to_lowercase())),
+            (None, None) => None,
+        };
+        options.startup_quiet = get_duration(cx, obj, "startupQuietMs")?;
+        // 0 means unlimited, the same as leaving it out.
+        options.max_events = get_index(cx, obj, "maxEvents")?.filter(|&max| max > 0);
+        options.debug_filters = get_bool(cx, obj, "debugFilters")?.unwrap_or(false);
+        options.debug_latency = get_bool(cx, obj, "debugLatency")?.unwrap_or(false);
+        options.dedupe_by = match get_string(cx, obj, "dedupeBy")?.as_deref() {
+            None => None,
+            Some("hwnd") => Some(DedupeKey::Hwnd),
+            Some("pid") => Some(DedupeKey::Pid),
+            Some("exe") => Some(DedupeKey::Exe),
+            Some(other) => {
+                return cx.throw_type_error(format!(
+                    "`dedupeBy` must be \"hwnd\", \"pid\" or \"exe\", got \"{}\"",
+                    other
+                ))
+            }
+        };
+        options.ndjson_path = get_string(cx, obj, "ndjsonPath")?.map(PathBuf::from);
+        options.ndjson_only = get_bool(cx, obj, "ndjsonOnly")?.unwrap_or(false);
+        if options.ndjson_only && options.ndjson_path.is_none() {
+            return cx.throw_type_error("`ndjsonOnly` requires `ndjsonPath`");
+        }
+        options.callback_retries = get_index(cx, obj, "callbackRetries")?.unwrap_or(0) as u32;
+        options.callback_retry_delay =
+            get_duration(cx, obj, "callbackRetryMs")?.unwrap_or(DEFAULT_CALLBACK_RETRY_DELAY);
+        options.thread_priority = match get_string(cx, obj, "threadPriority")? {
+            Some(level) => match priority::LEVELS.iter().find(|(name, _)| *name == level) {
+                Some(&(_, priority)) => Some(priority),
+                None => {
+                    let names: Vec<String> = priority::LEVELS
+                        .iter()
+                        .map(|(name, _)| format!("\"{}\"", name))
+                        .collect();
+                    return cx.throw_type_error(format!(
+                        "`threadPriority` must be one of {}, got \"{}\"",
+                        names.join(", "),
+                        level
+                    ));
+                }
+            },
+            None => None,
+        };
+        options.payload_format = match get_string(cx, obj, "payloadFormat")?.as_deref() {
+            None | Some("object") => PayloadFormat::Object,
+            Some("msgpack") => PayloadFormat::MessagePack,
+            Some(other) => {
+                return cx.throw_type_error(format!(
+                    "`payloadFormat` must be \"object\" or \"msgpack\", got \"{}\"",
+                    other
+                ))
+            }
+        };
+        options.delta = get_bool(cx, obj, "delta")?.unwrap_or(false);
+        if get_bool(cx, obj, "strictOrder")?.unwrap_or(false) {
+            let buffer_size = get_index(cx, obj, "strictOrderBufferSize")?
+                .unwrap_or(ordered::DEFAULT_BUFFER_SIZE);
+            if buffer_size == 0 {
+                return cx.throw_range_error("`strictOrderBufferSize` must be at least 1");
+            }
+            options.strict_order = Some(buffer_size);
+        }
+        // 0 turns the timeout off.
+        options.callback_timeout = match get_duration(cx, obj, "callbackTimeoutMs")? {
+            Some(timeout) if timeout.is_zero() => None,
+            Some(timeout) => Some(timeout),
+            None => Some(DEFAULT_CALLBACK_TIMEOUT),
+        };
+        options.usage_stats_by = match get_string(cx, obj, "usageStatsBy")?.as_deref() {
+            None => None,
+            Some("pid") => Some(UsageKey::Pid),
+            Some("exe") => Some(UsageKey::Exe),
+            Some(other) => {
+                return cx.throw_type_error(format!(
+                    "`usageStatsBy` must be \"pid\" or \"exe\", got \"{}\"",
+                    other
+                ))
+            }
+        };
+        options.dedupe_window = get_duration(cx, obj, "dedupeWindowMs")?;
+        if options.dedupe_window.is_some() && options.dedupe_by.is_none() {
+            options.dedupe_by = Some(DedupeKey::Hwnd);
+        }
+        if let Some(property) = obj.get_opt::<JsObject, _, _>(cx, "windowProperty")? {
+            options.window_property = Some(WindowProperty::from_js(cx, property)?);
+        }
+        if let Some(source) = get_string(cx, obj, "filterExpression")? {
+            match Expression::compile(&source) {
+                Ok(expression) => options.filter_expression = Some(expression),
+                Err(err) => {
+                    return cx.throw_error(format!("invalid `filterExpression`: {}", err))
+                }
+            }
+        }
+        if let Some(hwnd) = obj.get_opt::<JsValue, _, _>(cx, "ownerHwnd")? {
+            let hwnd = handle::js_to_hwnd(cx, hwnd)?;
+            if !window::is_window(handle::to_hwnd(hwnd)) {
+                return cx.throw_type_error(format!("`ownerHwnd` {} is not a window", hwnd));
+            }
+            options.owner_hwnd = Some(hwnd);
+        }
+        if let Some(hwnds) = obj.get_opt::<JsArray, _, _>(cx, "ignoreHwnds")? {
+            options.ignore_hwnds = Some(hwnd_set_from_js(cx, hwnds)?);
+        }
+        if options.strict_order.is_some() {
+            let conflicting = [
+                ("throttleMs", options.throttle.is_some()),
+                ("settleMs", options.settle.is_some()),
+                ("foregroundDebounceMs", options.foreground_debounce.is_some()),
+                ("nameChangeDebounceMs", options.name_change_debounce.is_some()),
+                ("delta", options.delta),
+            ];
+            if let Some((name, _)) = conflicting.iter().find(|(_, set)| *set) {
+                return cx.throw_type_error(format!(
+                    "`strictOrder` can't be combined with `{}`",
+                    name
+                ));
+            }
+        }
+        // Fields whose code this build was compiled without. Refuse rather
+        // than leave them silently `null`.
+        let gated = [
+            ("includeBrowserUrl", options.include_browser_url, "uia"),
+            ("includeTaskbarIndex", options.include_taskbar_index, "uia"),
+            ("includeAccessibleName", options.include_accessible_name, "uia"),
+            ("includeResourceUsage", options.include_resource_usage, "process-info"),
+            ("includeCommandLine", options.include_command_line, "process-info"),
+            ("includeParentProcess", options.include_parent_process, "process-info"),
+        ];
+        for &(name, requested, feature) in &gated {
+            if requested && !is_compiled_in(feature) {
+                return cx.throw_error(format!(
+                    "`{}` requires the `{}` feature, which this build doesn't include",
+                    name, feature
+                ));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+impl WindowProperty {
+    // `{ key: string, value?: number }`. Keys are looked up as strings, so
+    // properties set with an atom rather than a string key won't match.
+    fn from_js<'a, C: Context<'a>>(cx: &mut C, obj: Handle<'a, JsObject>) -> NeonResult<Self> {
+        let key = obj.get::<JsString, _, _>(cx, "key")?.value(cx);
+        if key.is_empty() {
+            return cx.throw_type_error("`windowProperty.key` must not be empty");
+        }
+
+        let value = obj
+            .get_opt::<JsNumber, _, _>(cx, "value")?
+            .map(|value| value.value(cx) as isize);
+
+        Ok(Self {
+            key: key.encode_utf16().chain(Some(0)).collect(),
+            value,
+        })
+    }
+}
+
+// The Cargo features that gate heavier enrichers, see Cargo.toml.
+pub const FEATURES: &[&str] = &["uia", "process-info"];
+
+pub fn is_compiled_in(feature: &str) -> bool {
+    (feature == "uia" && cfg!(feature = "uia"))
+        || (feature == "process-info" && cfg!(feature = "process-info"))
+}
+
+// Read an optional boolean property, throwing a `TypeError` if it is present
+// but not a boolean.
+pub fn get_bool<'a, C: Context<'a>>(
+    cx: &mut C,
+    obj: Handle<'a, JsObject>,
+    key: &str,
+) -> NeonResult<Option<bool>> {
+    Ok(obj
+        .get_opt::<JsBoolean, _, _>(cx, key)?
+        .map(|value| value.value(cx)))
+}
+
+pub fn get_string<'a, C: Context<'a>>(
+    cx: &mut C,
+    obj: Handle<'a, JsObject>,
+    key: &str,
+) -> NeonResult<Option<String>> {
+    Ok(obj
+        .get_opt::<JsString, _, _>(cx, key)?
+        .map(|value| value.value(cx)))
+}
+
+// Read an optional millisecond count as a `Duration`, throwing a `RangeError`
+// for negative or non-finite values.
+fn get_duration<'a, C: Context<'a>>(
+    cx: &mut C,
+    obj: Handle<'a, JsObject>,
+    key: &str,
+) -> NeonResult<Option<Duration>> {
+    let ms = match obj.get_opt::<JsNumber, _, _>(cx, key)? {
+        Some(ms) => ms.value(cx),
+        None => return Ok(None),
+    };
+
+    if !ms.is_finite() || ms < 0.0 {
+        return cx.throw_range_error(format!("`{}` must be a non-negative number", key));
+    }
+
+    Ok(Some(Duration::from_millis(ms as u64)))
+}
+
+// Read an optional non-negative integer, throwing a `RangeError` otherwise.
+pub fn get_index<'a, C: Context<'a>>(
+    cx: &mut C,
+    obj: Handle<'a, JsObject>,
+    key: &str,
+) -> NeonResult<Option<usize>> {
+    let value = match obj.get_opt::<JsNumber, _, _>(cx, key)? {
+        Some(value) => value.value(cx),
+        None => return Ok(None),
+    };
+
+    if value < 0.0 || value.fract() != 0.0 || !value.is_finite() {
+        return cx.throw_range_error(format!("`{}` must be a non-negative integer", key));
+    }
+
+    Ok(Some(value as usize))
+}
+
+// Parse an array of HWNDs, given either as the decimal strings used in event
+// payloads or as numbers.
+pub fn hwnd_set_from_js<'a, C: Context<'a>>(
+    cx: &mut C,
+    hwnds: Handle<'a, JsArray>,
+) -> NeonResult<HashSet<isize>> {
+    let mut set = HashSet::new();
+    for hwnd in hwnds.to_vec(cx)? {
+        set.insert(handle::js_to_hwnd(cx, hwnd)?);
+    }
+
+    Ok(set)
+}
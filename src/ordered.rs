@@ -0,0 +1,132 @@
+use std::sync::atomic::Ordering;
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, UnboundedReceiver};
+use wineventhook::WindowEvent;
+
+use crate::DROPPED_EVENTS;
+
+// The buffer `strictOrder` uses unless `strictOrderBufferSize` is given.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+// Where a WinEvent came in the order it arrived from the hooks.
+#[derive(Clone, Copy)]
+pub struct Sequence {
+    pub seq: u64,
+    // Events that overflowed the buffer since the previous one was queued.
+    pub dropped_before: u64,
+}
+
+// What a listener task reads WinEvents from: straight from the hooks, or, for
+// `strictOrder`, through `forward`'s bounded buffer.
+pub enum EventSource {
+    Direct(UnboundedReceiver<WindowEvent>),
+    Ordered(Receiver<(WindowEvent, Sequence)>),
+}
+
+impl EventSource {
+    pub fn new(raw_rx: UnboundedReceiver<WindowEvent>, buffer_size: Option<usize>) -> Self {
+        match buffer_size {
+            Some(buffer_size) => EventSource::Ordered(forward(raw_rx, buffer_size)),
+            None => EventSource::Direct(raw_rx),
+        }
+    }
+
+    pub async fn recv(&mut self) -> Option<(WindowEvent, Option<Sequence>)> {
+        match self {
+            EventSource::Direct(rx) => rx.recv().await.map(|event| (event, None)),
+            EventSource::Ordered(rx) => rx
+                .recv()
+                .await
+                .map(|(event, sequence)| (event, Some(sequence))),
+        }
+    }
+}
+
+// Number events as they come off the hooks and queue them into a buffer of
+// `buffer_size`, so the order the callback sees is exactly the order the
+// hooks reported. A callback that falls behind fills the buffer; events that
+// arrive then are dropped rather than coalesced or queued without bound, and
+// counted in `droppedEventCount()` and the next delivered event's
+// `droppedBefore`. Their `seq`s are skipped.
+fn forward<T: Send + 'static>(
+    mut raw_rx: UnboundedReceiver<T>,
+    buffer_size: usize,
+) -> Receiver<(T, Sequence)> {
+    let (tx, rx) = mpsc::channel(buffer_size);
+    tokio::spawn(async move {
+        let mut seq = 0;
+        let mut dropped_before = 0;
+        while let Some(event) = raw_rx.recv().await {
+            seq += 1;
+            let sequence = Sequence {
+                seq,
+                dropped_before,
+            };
+            match tx.try_send((event, sequence)) {
+                Ok(()) => dropped_before = 0,
+                Err(TrySendError::Full(_)) => {
+                    dropped_before += 1;
+                    DROPPED_EVENTS.fetch_add(1, Ordering::SeqCst);
+                }
+                // The listener task is gone.
+                Err(TrySendError::Closed(_)) => break,
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn numbers_events_in_arrival_order() {
+        let (raw_tx, raw_rx) = mpsc::unbounded_channel();
+        let mut rx = forward(raw_rx, DEFAULT_BUFFER_SIZE);
+        for event in 0..1000 {
+            raw_tx.send(event).unwrap();
+        }
+        drop(raw_tx);
+
+        let mut expected = 0;
+        while let Some((event, sequence)) = rx.recv().await {
+            assert_eq!(event, expected);
+            assert_eq!(sequence.seq, expected + 1);
+            assert_eq!(sequence.dropped_before, 0);
+            expected += 1;
+        }
+        assert_eq!(expected, 1000);
+    }
+
+    #[tokio::test]
+    async fn counts_overflow_in_the_next_queued_event() {
+        let dropped_at_start = DROPPED_EVENTS.load(Ordering::SeqCst);
+        let (raw_tx, raw_rx) = mpsc::unbounded_channel();
+        // Everything arrives before the forwarder first runs, so with nobody
+        // reading, only the first 4 fit.
+        for event in 1..=10 {
+            raw_tx.send(event).unwrap();
+        }
+        let mut rx = forward(raw_rx, 4);
+
+        for expected in 1..=4 {
+            let (event, sequence) = rx.recv().await.unwrap();
+            assert_eq!(event, expected);
+            assert_eq!(sequence.seq, expected);
+            assert_eq!(sequence.dropped_before, 0);
+        }
+        assert_eq!(DROPPED_EVENTS.load(Ordering::SeqCst) - dropped_at_start, 6);
+
+        raw_tx.send(11).unwrap();
+        let (event, sequence) = rx.recv().await.unwrap();
+        assert_eq!(event, 11);
+        assert_eq!(sequence.seq, 11);
+        assert_eq!(sequence.dropped_before, 6);
+
+        // The count starts over once it has been reported.
+        raw_tx.send(12).unwrap();
+        let (_, sequence) = rx.recv().await.unwrap();
+        assert_eq!(sequence.seq, 12);
+        assert_eq!(sequence.dropped_before, 0);
+    }
+}
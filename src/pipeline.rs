@@ -0,0 +1,575 @@
+use std::ptr::{self, NonNull};
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+use winapi::shared::windef::HWND;
+use wineventhook::{raw_event, AccessibleObjectId, WindowEvent};
+
+use crate::event::{
+    EventBuilder, EventType, ExitReason, ForegroundEvent, RawEvent, Rect, Transition,
+    WindowAnchor,
+};
+use crate::cause;
+use crate::filter::{self, FilterReport};
+use crate::fullscreen::FullscreenTracker;
+use crate::idle::{self, IdleTracker};
+use crate::list::{WindowChange, WindowListPoller};
+use crate::options::{AwayFrom, DedupeKey, StartOptions, UsageKey};
+use crate::session::SessionChange;
+use crate::state::SharedState;
+use crate::throttle::{Debounce, Throttle, TypedDebounce};
+use crate::track::WindowTracker;
+use crate::{handle, process, window};
+
+// Everything a listener task does between receiving an input (a WinEvent or a
+// timer tick) and handing a payload to the callback: filtering, enrichment,
+// throttling and bookkeeping.
+//
+// It is synchronous and holds only plain values, so the task's select loop
+// stays small and the window handles it works with never live across an
+// await.
+pub struct Pipeline {
+    options: StartOptions,
+    state: SharedState,
+    builder: EventBuilder,
+    tracker: Option<WindowTracker>,
+    idle_tracker: Option<IdleTracker>,
+    throttle: Option<Throttle>,
+    settle: Option<Debounce>,
+    debounce: Option<TypedDebounce>,
+    fullscreen: Option<FullscreenTracker>,
+    window_list: Option<WindowListPoller>,
+    // The `dedupeBy` key of the last delivered foreground event, and when it
+    // was last seen, suppressed repeats included.
+    last_key: Option<DedupeValue>,
+    last_key_seen: Option<Instant>,
+    // The last delivered foreground window, for `includeTransition`.
+    last_anchor: Option<WindowAnchor>,
+    // The process of the last delivered foreground window.
+    last_pid: Option<u32>,
+    // The clipboard sequence number at the last delivered foreground change.
+    last_clipboard_sequence: Option<u32>,
+    // The last delivered foreground window, for `includePreviousExitReason`.
+    last_hwnd: Option<isize>,
+    // The `waitForExe` executable, until it has been seen.
+    waiting_for_exe: Option<String>,
+    // The `awayFrom` process while it has the foreground.
+    away_target_pid: Option<u32>,
+}
+
+impl Pipeline {
+    pub fn new(options: StartOptions, state: SharedState) -> Self {
+        let mut pipeline = Self {
+            builder: EventBuilder::new(&options),
+            tracker: options.track_hwnd.map(WindowTracker::new),
+            idle_tracker: options.idle_threshold.map(IdleTracker::new),
+            throttle: options.throttle.map(Throttle::new),
+            settle: options.settle.map(Debounce::new),
+            debounce: TypedDebounce::new(options.foreground_debounce, options.name_change_debounce),
+            fullscreen: (options.fullscreen_events && options.track_hwnd.is_none())
+                .then(FullscreenTracker::new),
+            window_list: options.window_list_interval.map(WindowListPoller::new),
+            last_key: None,
+            last_key_seen: None,
+            last_anchor: None,
+            last_pid: None,
+            last_clipboard_sequence: None,
+            last_hwnd: None,
+            waiting_for_exe: options.wait_for_exe.clone(),
+            away_target_pid: None,
+            options,
+            state,
+        };
+        // The target may already be in front when the listener starts.
+        pipeline.away_target_pid = pipeline.away_from(window::get_foreground_window());
+
+        pipeline
+    }
+
+    // Swap in new options between events. Components whose settings didn't
+    // change keep their state, so e.g. a pending throttled event survives an
+    // unrelated change. What the task was started for (the tracked window,
+    // the hooks `fullscreenEvents` adds, `listenerId`, the NDJSON sink,
+    // `maxEvents`, `startupQuietMs`, `waitForExe`, `threadPriority` and how
+    // events are handed to the callback) stays as it was.
+    pub fn reconfigure(&mut self, mut options: StartOptions) {
+        options.track_hwnd = self.options.track_hwnd;
+        options.listener_id = self.options.listener_id;
+        options.ndjson_path = self.options.ndjson_path.clone();
+        options.ndjson_only = self.options.ndjson_only;
+        options.max_events = self.options.max_events;
+        options.startup_quiet = self.options.startup_quiet;
+        options.fullscreen_events = self.options.fullscreen_events;
+        options.wait_for_exe = self.options.wait_for_exe.clone();
+        options.callback_retries = self.options.callback_retries;
+        options.callback_retry_delay = self.options.callback_retry_delay;
+        options.callback_timeout = self.options.callback_timeout;
+        options.payload_format = self.options.payload_format;
+        options.delta = self.options.delta;
+        options.strict_order = self.options.strict_order;
+        // The new options were only checked against `strictOrder` if they set
+        // it themselves, so drop anything that would hold events back.
+        if options.strict_order.is_some() {
+            options.throttle = None;
+            options.settle = None;
+            options.foreground_debounce = None;
+            options.name_change_debounce = None;
+        }
+        options.thread_priority = self.options.thread_priority;
+
+        if options.idle_threshold != self.options.idle_threshold {
+            self.idle_tracker = options.idle_threshold.map(IdleTracker::new);
+        }
+        if options.throttle != self.options.throttle {
+            self.throttle = options.throttle.map(Throttle::new);
+        }
+        if options.foreground_debounce != self.options.foreground_debounce
+            || options.name_change_debounce != self.options.name_change_debounce
+        {
+            self.debounce =
+                TypedDebounce::new(options.foreground_debounce, options.name_change_debounce);
+        }
+        if options.window_list_interval != self.options.window_list_interval {
+            self.window_list = options.window_list_interval.map(WindowListPoller::new);
+        }
+        if options.settle != self.options.settle {
+            self.settle = options.settle.map(Debounce::new);
+        }
+        if options.dedupe_by != self.options.dedupe_by {
+            self.last_key = None;
+            self.last_key_seen = None;
+        }
+        self.builder.reconfigure(&options);
+
+        self.options = options;
+    }
+
+    pub fn tracks_idle(&self) -> bool {
+        self.idle_tracker.is_some()
+    }
+
+    // The earliest timer any component is waiting on.
+    pub fn deadline(&self) -> Option<Instant> {
+        [
+            self.throttle.as_ref().and_then(Throttle::deadline),
+            self.settle.as_ref().and_then(Debounce::deadline),
+            self.debounce.as_ref().and_then(TypedDebounce::deadline),
+            self.fullscreen.as_ref().and_then(FullscreenTracker::deadline),
+            self.window_list.as_ref().map(WindowListPoller::deadline),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+    }
+
+    pub fn on_window_event(&mut self, event: &WindowEvent) -> Option<ForegroundEvent> {
+        if event.object_type() != AccessibleObjectId::Window || self.is_suspended() {
+            return None;
+        }
+        let received = Instant::now();
+
+        let window = event
+            .window_handle()
+            .map_or_else(ptr::null_mut, NonNull::as_ptr);
+
+        if let Some(tracker) = &mut self.tracker {
+            let event_type = tracker.classify(event_id(event), window)?;
+            let mut payload = self.builder.build(tracker.hwnd(), &self.options);
+            payload.event_type = event_type;
+            if self.options.raw_event {
+                payload.raw_event = Some(to_raw_event(event));
+            }
+            if self.options.debug_latency {
+                payload.processing_latency_us = Some(received.elapsed().as_micros() as u64);
+            }
+            return match &mut self.debounce {
+                Some(debounce) => debounce.push(payload),
+                None => Some(payload),
+            };
+        }
+
+        if let Some(key) = self.options.usage_stats_by {
+            if event_id(event) == raw_event::SYSTEM_FOREGROUND {
+                let (key, process_start) = self.usage_key(key, window);
+                if let Some(usage) = &mut self.state.lock().unwrap().usage {
+                    usage.switch_to(key, process_start);
+                }
+            }
+        }
+
+        if let Some(exe) = &self.waiting_for_exe {
+            if event_id(event) != raw_event::SYSTEM_FOREGROUND {
+                return None;
+            }
+            let exe = exe.clone();
+            if !self.is_exe(window, &exe) {
+                return None;
+            }
+            self.waiting_for_exe = None;
+            if !self.options.include_waited_event {
+                return None;
+            }
+        }
+
+        // Location changes are only hooked to follow the foreground window's
+        // fullscreen state.
+        if let Some(fullscreen) = &mut self.fullscreen {
+            if window == window::get_foreground_window() {
+                fullscreen.observe(window);
+            }
+            if event_id(event) == raw_event::OBJECT_LOCATIONCHANGE {
+                return None;
+            }
+        }
+
+        // Only the foreground moving from the target to another process gets
+        // past here in `awayFromPid`/`awayFromExe` mode. The transient null
+        // foreground during a switch doesn't count as leaving.
+        let mut left_pid = None;
+        if self.options.away_from.is_some() {
+            if event_id(event) != raw_event::SYSTEM_FOREGROUND || window.is_null() {
+                return None;
+            }
+            let previous = self.away_target_pid.take();
+            self.away_target_pid = self.away_from(window);
+            if self.away_target_pid.is_some() {
+                return None;
+            }
+            left_pid = Some(previous?);
+        }
+
+        let mut report = FilterReport::new(self.options.debug_filters);
+        let ignored = {
+            let state = self.state.lock().unwrap();
+            let hwnd = handle::from_hwnd(window);
+            (!state.ignored_hwnds.is_empty()).then(|| state.ignored_hwnds.contains(&hwnd))
+        };
+        filter::evaluate(window, &self.options, ignored, &mut self.builder, &mut report);
+
+        if let Some(key) = self.options.dedupe_by {
+            if !report.is_done() {
+                let value = self.dedupe_value(key, window);
+                let mut repeated = self.last_key.is_some() && self.last_key == value;
+                // With `dedupeWindowMs`, only a quick repeat is suppressed, and
+                // every repeat extends the window.
+                if let Some(interval) = self.options.dedupe_window {
+                    if repeated {
+                        let now = Instant::now();
+                        repeated = self.last_key_seen.is_none_or(|seen| now - seen < interval);
+                        self.last_key_seen = Some(now);
+                    }
+                }
+                report.record("dedupeBy", !repeated);
+            }
+        }
+
+        // In `debugFilters` mode nothing is dropped; the report is attached
+        // instead.
+        if !self.options.debug_filters && !report.passed() {
+            return None;
+        }
+
+        let mut payload = self.builder.build(window, &self.options);
+        payload.left_pid = left_pid;
+        if self.options.include_cause {
+            payload.cause = Some(cause::classify(window));
+        }
+        if let Some(threshold) = self.options.focus_stolen_threshold {
+            if is_focus_stolen(event, window, threshold) {
+                payload.event_type = EventType::FocusStolen;
+            }
+        }
+        if self.options.debug_filters {
+            payload.filters = Some(report.into_results());
+        }
+        if self.options.raw_event {
+            payload.raw_event = Some(to_raw_event(event));
+        }
+        if self.options.debug_latency {
+            payload.processing_latency_us = Some(received.elapsed().as_micros() as u64);
+        }
+        let payload = match &mut self.throttle {
+            Some(throttle) => throttle.push(payload)?,
+            None => payload,
+        };
+        let payload = match &mut self.debounce {
+            Some(debounce) => debounce.push(payload)?,
+            None => payload,
+        };
+        Some(self.settle(payload))
+    }
+
+    pub fn on_idle_tick(&mut self) -> Option<ForegroundEvent> {
+        let event = self.idle_tracker.as_mut().and_then(IdleTracker::poll)?;
+        Some(event).filter(|_| !self.is_suspended() && self.waiting_for_exe.is_none())
+    }
+
+    pub fn on_session_change(&mut self, change: SessionChange) -> Option<ForegroundEvent> {
+        Some(ForegroundEvent::session_change(change))
+            .filter(|_| !self.is_suspended() && self.waiting_for_exe.is_none())
+    }
+
+    // Fire whichever timer is due. If several are, the others fire on the next
+    // call, since their deadlines have passed too.
+    pub fn on_deadline(&mut self) -> Option<ForegroundEvent> {
+        let now = Instant::now();
+        let is_due = |deadline: Option<Instant>| deadline.is_some_and(|deadline| deadline <= now);
+
+        if let Some(throttle) = &mut self.throttle {
+            if is_due(throttle.deadline()) {
+                let payload = throttle.expire()?;
+                let payload = match &mut self.debounce {
+                    Some(debounce) => debounce.push(payload)?,
+                    None => payload,
+                };
+                return Some(self.settle(payload));
+            }
+        }
+
+        if let Some(debounce) = &mut self.debounce {
+            if is_due(debounce.deadline()) {
+                let payload = debounce.expire_due(now)?;
+                // Tracked-window events aren't settled.
+                if self.tracker.is_some() {
+                    return Some(payload);
+                }
+                return Some(self.settle(payload));
+            }
+        }
+
+        if let Some(settle) = &mut self.settle {
+            if is_due(settle.deadline()) {
+                let mut payload = settle.expire()?;
+                payload.settled = Some(true);
+                return Some(payload);
+            }
+        }
+
+        if let Some(window_list) = &mut self.window_list {
+            if is_due(Some(window_list.deadline())) {
+                let change = window_list.next_change()?;
+                if self.is_suspended() || self.waiting_for_exe.is_some() {
+                    return None;
+                }
+                return Some(match change {
+                    WindowChange::Opened(hwnd) => {
+                        let window = handle::to_hwnd(hwnd);
+                        let mut payload = self.builder.build(window, &self.options);
+                        payload.event_type = EventType::WindowOpened;
+                        payload
+                    }
+                    WindowChange::Closed(hwnd) => ForegroundEvent::window_closed(hwnd),
+                });
+            }
+        }
+
+        if let Some(fullscreen) = &mut self.fullscreen {
+            if is_due(fullscreen.deadline()) {
+                let (window, event_type) = fullscreen.expire()?;
+                let mut payload = self.builder.build(window, &self.options);
+                payload.event_type = event_type;
+                return Some(payload);
+            }
+        }
+
+        None
+    }
+
+    // In `settleMs` mode, mark a foreground change as unsettled and start
+    // waiting for it to settle.
+    fn settle(&mut self, mut payload: ForegroundEvent) -> ForegroundEvent {
+        if let Some(settle) = &mut self.settle {
+            payload.settled = Some(false);
+            settle.push(payload.clone());
+        }
+        payload
+    }
+
+    // Stop timing the current foreground window once the task is done.
+    pub fn finish(&mut self) {
+        if let Some(usage) = &mut self.state.lock().unwrap().usage {
+            usage.switch_to(None, None);
+        }
+    }
+
+    // The window's process if it is the `awayFrom` target. With
+    // `awayFromExe`, moving between processes of the target executable isn't
+    // leaving it.
+    fn away_from(&mut self, window: HWND) -> Option<u32> {
+        let pid = window::get_window_process_id(window).ok()?;
+        let is_target = match self.options.away_from.clone()? {
+            AwayFrom::Pid(target) => pid == target,
+            AwayFrom::Exe(exe) => self.is_exe(window, &exe),
+        };
+        is_target.then_some(pid)
+    }
+
+    // Whether the window belongs to `exe`, a lowercased file name or full
+    // path.
+    fn is_exe(&mut self, window: HWND, exe: &str) -> bool {
+        let path = match window::get_window_process_id(window)
+            .ok()
+            .and_then(|pid| self.builder.exe_path(pid))
+        {
+            Some(path) => path,
+            None => return false,
+        };
+
+        if exe.contains('\\') || exe.contains('/') {
+            path.to_string_lossy().to_lowercase() == exe.replace('/', "\\")
+        } else {
+            path.file_name().is_some_and(|name| name.to_string_lossy().to_lowercase() == exe)
+        }
+    }
+
+    // The key and, for PID keys, the process's start time.
+    fn usage_key(&mut self, key: UsageKey, window: HWND) -> (Option<String>, Option<SystemTime>) {
+        let pid = match window::get_window_process_id(window) {
+            Ok(pid) => pid,
+            Err(_) => return (None, None),
+        };
+        let exe_path = match key {
+            UsageKey::Pid => None,
+            UsageKey::Exe => self.builder.exe_path(pid),
+        };
+        match exe_path {
+            Some(path) => (Some(path.to_string_lossy().into_owned()), None),
+            None => (
+                Some(pid.to_string()),
+                process::get_process_start_time(pid).ok(),
+            ),
+        }
+    }
+
+    // In `onlyWhenSelfBackground` mode nothing is delivered while our own
+    // process is in the foreground.
+    fn is_suspended(&self) -> bool {
+        self.options.only_when_self_background && window::is_own_process_foreground()
+    }
+
+    fn dedupe_value(&mut self, key: DedupeKey, window: HWND) -> Option<DedupeValue> {
+        match key {
+            DedupeKey::Hwnd => Some(DedupeValue::Id(handle::from_hwnd(window))),
+            DedupeKey::Pid => window::get_window_process_id(window)
+                .ok()
+                .map(|pid| DedupeValue::Id(pid as isize)),
+            DedupeKey::Exe => {
+                let pid = window::get_window_process_id(window).ok()?;
+                let path = self.builder.exe_path(pid)?;
+                let name = path.file_name()?.to_string_lossy().to_lowercase();
+                Some(DedupeValue::Exe(name))
+            }
+        }
+    }
+
+    // Called right before a payload is handed to the callback. A throttled
+    // event may be delivered well after it was built, so liveness is checked
+    // again here.
+    pub fn on_deliver(&mut self, payload: &mut ForegroundEvent) {
+        payload.listener_id = self.options.listener_id;
+        if let Some(hwnd) = payload.hwnd {
+            payload.is_alive = Some(window::is_window(handle::to_hwnd(hwnd)));
+        }
+
+        // A settled repeat was already accounted for when first delivered.
+        let is_foreground =
+            payload.event_type.is_foreground_change() && payload.settled != Some(true);
+        if is_foreground && !self.options.minimal {
+            let pid = payload
+                .hwnd
+                .and_then(|hwnd| window::get_window_process_id(handle::to_hwnd(hwnd)).ok());
+            let previous = self.last_pid;
+            payload.same_process_as_previous = Some(match (previous, pid) {
+                (Some(previous), Some(pid)) => Some(previous == pid),
+                _ => None,
+            });
+            self.last_pid = pid;
+        }
+
+        if is_foreground && self.options.include_clipboard_change {
+            let sequence = window::get_clipboard_sequence_number();
+            payload.clipboard_changed = Some(match (self.last_clipboard_sequence, sequence) {
+                (Some(previous), Some(sequence)) => Some(previous != sequence),
+                _ => None,
+            });
+            self.last_clipboard_sequence = sequence;
+        }
+
+        if is_foreground && self.options.include_previous_exit_reason {
+            let previous = std::mem::replace(&mut self.last_hwnd, payload.hwnd);
+            payload.previous_exit_reason =
+                Some(previous.map(|previous| ExitReason::of(handle::to_hwnd(previous))));
+        }
+
+        if is_foreground && self.options.include_transition {
+            // The rect at delivery, so the next event's `from` is where the
+            // window was while it was in front.
+            let to = WindowAnchor {
+                hwnd: payload.hwnd,
+                rect: payload
+                    .hwnd
+                    .and_then(|hwnd| window::get_window_rect(handle::to_hwnd(hwnd)).ok())
+                    .map(Rect::from),
+            };
+            payload.transition = Some(Transition {
+                from: self.last_anchor.replace(to.clone()),
+                to,
+            });
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.last = Some(payload.clone());
+            if is_foreground {
+                state.push_history(payload);
+            }
+        }
+
+        if is_foreground {
+            if let (Some(key), Some(hwnd)) = (self.options.dedupe_by, payload.hwnd) {
+                self.last_key = self.dedupe_value(key, handle::to_hwnd(hwnd));
+                self.last_key_seen = Some(Instant::now());
+            }
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum DedupeValue {
+    Id(isize),
+    // Lowercased executable file name.
+    Exe(String),
+}
+
+// A window taking the foreground is assumed to be user-initiated if there was
+// input shortly before, or if the activation came from another thread, such
+// as the shell handling Alt+Tab or a taskbar click. What's left is a window
+// whose own thread brought it to the front on its own.
+fn is_focus_stolen(event: &WindowEvent, window: HWND, threshold: Duration) -> bool {
+    let idle = match idle::get_idle_time() {
+        Ok(idle) => idle,
+        Err(_) => return false,
+    };
+
+    let self_activated =
+        window::get_window_thread_id(window).ok() == Some(event.raw.thread_id);
+    idle >= threshold && self_activated
+}
+
+// The raw `EVENT_*` constant of a WinEvent, for comparing against `raw_event`,
+// whose constants are `i32`s where the hook reports a `u32`.
+pub fn event_id(event: &WindowEvent) -> i32 {
+    event.raw.event_id as i32
+}
+
+fn to_raw_event(event: &WindowEvent) -> RawEvent {
+    let raw = &event.raw;
+    RawEvent {
+        event: raw.event_id,
+        hwnd: Some(handle::from_hwnd(raw.window_handle)).filter(|&hwnd| hwnd != 0),
+        id_object: raw.object_id,
+        id_child: raw.child_id,
+        id_event_thread: raw.thread_id,
+        dwms_event_time: raw.timestamp,
+    }
+}
@@ -0,0 +1,59 @@
+use std::io;
+
+use winapi::um::{
+    processthreadsapi::{GetCurrentThread, GetThreadPriority, SetThreadPriority},
+    winbase::{
+        THREAD_PRIORITY_ABOVE_NORMAL, THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_ERROR_RETURN,
+        THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_LOWEST, THREAD_PRIORITY_NORMAL,
+    },
+};
+
+// The `threadPriority` levels, by option value. Time-critical and idle are
+// left out: either can starve the other tasks sharing the runtime's threads.
+pub const LEVELS: &[(&str, i32)] = &[
+    ("lowest", THREAD_PRIORITY_LOWEST as i32),
+    ("belowNormal", THREAD_PRIORITY_BELOW_NORMAL as i32),
+    ("normal", THREAD_PRIORITY_NORMAL as i32),
+    ("aboveNormal", THREAD_PRIORITY_ABOVE_NORMAL as i32),
+    ("highest", THREAD_PRIORITY_HIGHEST as i32),
+];
+
+// Runs the current thread at a priority until dropped, then restores the one
+// it had.
+//
+// Listener tasks share the runtime's worker threads and can move between
+// them at every await, so a task can't own a thread's priority. Instead the
+// guard is held only around each synchronous stretch of processing, and must
+// never be held across an await. The hook thread that receives WinEvents
+// belongs to `wineventhook` and isn't affected.
+pub struct PriorityGuard {
+    previous: Option<i32>,
+}
+
+impl PriorityGuard {
+    pub fn new(priority: i32) -> io::Result<Self> {
+        let thread = unsafe { GetCurrentThread() };
+        let previous = unsafe { GetThreadPriority(thread) };
+        if previous == THREAD_PRIORITY_ERROR_RETURN as i32 {
+            return Err(io::Error::last_os_error());
+        }
+        if previous == priority {
+            return Ok(Self { previous: None });
+        }
+
+        if unsafe { SetThreadPriority(thread, priority) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            previous: Some(previous),
+        })
+    }
+}
+
+impl Drop for PriorityGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            unsafe { SetThreadPriority(GetCurrentThread(), previous) };
+        }
+    }
+}
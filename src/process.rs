@@ -0,0 +1,381 @@
+use std::{
+    ffi::OsString,
+    io, mem,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use winapi::{
+    shared::minwindef::{DWORD, FALSE, FILETIME, MAX_PATH},
+    um::{
+        fileapi::{GetLongPathNameW, QueryDosDeviceW},
+        handleapi::CloseHandle,
+        processthreadsapi::{GetProcessTimes, OpenProcess},
+        synchapi::WaitForSingleObject,
+        winbase::{QueryFullProcessImageNameW, WAIT_OBJECT_0},
+        winnt::{HANDLE, PROCESS_QUERY_LIMITED_INFORMATION},
+    },
+};
+
+// The rest is only needed for the `process-info` enrichers.
+#[cfg(feature = "process-info")]
+use std::ptr;
+#[cfg(feature = "process-info")]
+use winapi::{
+    shared::{minwindef::ULONG, ntdef::NTSTATUS},
+    um::{
+        memoryapi::ReadProcessMemory,
+        psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+        winnt::PROCESS_VM_READ,
+    },
+};
+
+#[cfg(feature = "process-info")]
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtQueryInformationProcess(
+        process: HANDLE,
+        class: u32,
+        info: *mut std::ffi::c_void,
+        info_len: ULONG,
+        return_len: *mut ULONG,
+    ) -> NTSTATUS;
+}
+
+// An open process handle that is closed when dropped.
+pub struct ProcessHandle(HANDLE);
+
+impl ProcessHandle {
+    pub fn open(pid: u32, access: DWORD) -> io::Result<Self> {
+        let handle = unsafe { OpenProcess(access, FALSE, pid) };
+        if handle.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Self(handle))
+        }
+    }
+
+    pub fn as_raw(&self) -> HANDLE {
+        self.0
+    }
+
+    // Needs `SYNCHRONIZE` access.
+    pub fn has_exited(&self) -> bool {
+        unsafe { WaitForSingleObject(self.0, 0) == WAIT_OBJECT_0 }
+    }
+}
+
+// Process handles aren't tied to the thread that opened them.
+unsafe impl Send for ProcessHandle {}
+
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+// Full Win32 path of the process's executable.
+pub fn get_process_image_path(pid: u32) -> io::Result<PathBuf> {
+    let process = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION)?;
+
+    let mut path = vec![0u16; MAX_PATH];
+    loop {
+        let mut len = path.len() as DWORD;
+        let result =
+            unsafe { QueryFullProcessImageNameW(process.as_raw(), 0, path.as_mut_ptr(), &mut len) };
+        if result != 0 {
+            path.truncate(len as usize);
+            return Ok(PathBuf::from(OsString::from_wide(&path)));
+        }
+
+        let err = io::Error::last_os_error();
+        // ERROR_INSUFFICIENT_BUFFER: long paths can exceed MAX_PATH.
+        if err.raw_os_error() != Some(122) || path.len() >= 32 * 1024 {
+            return Err(err);
+        }
+        path.resize(path.len() * 2, 0);
+    }
+}
+
+// When the process was started.
+pub fn get_process_start_time(pid: u32) -> io::Result<SystemTime> {
+    let times = get_process_times(pid)?;
+    Ok(filetime_to_system_time(times.creation))
+}
+
+// Total CPU time the process has used so far, in kernel and user mode.
+#[cfg(feature = "process-info")]
+pub fn get_process_cpu_time(pid: u32) -> io::Result<Duration> {
+    let times = get_process_times(pid)?;
+    Ok(filetime_to_duration(times.kernel) + filetime_to_duration(times.user))
+}
+
+struct ProcessFiletimes {
+    creation: FILETIME,
+    #[cfg(feature = "process-info")]
+    kernel: FILETIME,
+    #[cfg(feature = "process-info")]
+    user: FILETIME,
+}
+
+fn get_process_times(pid: u32) -> io::Result<ProcessFiletimes> {
+    let process = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION)?;
+
+    let mut creation: FILETIME = unsafe { mem::zeroed() };
+    let mut exit: FILETIME = unsafe { mem::zeroed() };
+    let mut kernel: FILETIME = unsafe { mem::zeroed() };
+    let mut user: FILETIME = unsafe { mem::zeroed() };
+    let result = unsafe {
+        GetProcessTimes(
+            process.as_raw(),
+            &mut creation,
+            &mut exit,
+            &mut kernel,
+            &mut user,
+        )
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ProcessFiletimes {
+        creation,
+        #[cfg(feature = "process-info")]
+        kernel,
+        #[cfg(feature = "process-info")]
+        user,
+    })
+}
+
+// Current working set size, in bytes. Older versions of Windows also require
+// `PROCESS_VM_READ`, which is asked for first.
+#[cfg(feature = "process-info")]
+pub fn get_process_working_set(pid: u32) -> io::Result<u64> {
+    let process = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ)
+        .or_else(|_| ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION))?;
+
+    let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { mem::zeroed() };
+    let size = mem::size_of::<PROCESS_MEMORY_COUNTERS>() as DWORD;
+    counters.cb = size;
+    if unsafe { GetProcessMemoryInfo(process.as_raw(), &mut counters, size) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(counters.WorkingSetSize as u64)
+}
+
+// `PROCESS_BASIC_INFORMATION` from winternl.h, which winapi doesn't define.
+#[cfg(feature = "process-info")]
+#[repr(C)]
+struct ProcessBasicInformation {
+    exit_status: NTSTATUS,
+    peb_base_address: usize,
+    affinity_mask: usize,
+    base_priority: i32,
+    unique_process_id: usize,
+    inherited_from_unique_process_id: usize,
+}
+
+// `UNICODE_STRING` as laid out in another process of the same bitness.
+#[cfg(feature = "process-info")]
+#[repr(C)]
+struct UnicodeString {
+    // In bytes, without a null terminator.
+    length: u16,
+    maximum_length: u16,
+    buffer: usize,
+}
+
+// Undocumented offsets of `PEB::ProcessParameters` and
+// `RTL_USER_PROCESS_PARAMETERS::CommandLine`. They have been stable since
+// Windows XP, but nothing guarantees they stay that way.
+#[cfg(all(feature = "process-info", target_pointer_width = "64"))]
+const PROCESS_PARAMETERS_OFFSET: usize = 0x20;
+#[cfg(all(feature = "process-info", target_pointer_width = "64"))]
+const COMMAND_LINE_OFFSET: usize = 0x70;
+#[cfg(all(feature = "process-info", target_pointer_width = "32"))]
+const PROCESS_PARAMETERS_OFFSET: usize = 0x10;
+#[cfg(all(feature = "process-info", target_pointer_width = "32"))]
+const COMMAND_LINE_OFFSET: usize = 0x40;
+
+// The command line the process was started with, read from its PEB.
+//
+// This is best-effort in every way: it needs `PROCESS_VM_READ`, which is
+// denied for elevated and protected processes unless we're elevated too; it
+// relies on undocumented structure offsets; a 32-bit build can't read a 64-bit
+// process; and a process is free to overwrite its own command line after
+// starting, so the value isn't necessarily what it was launched with. WMI
+// would cover some of the access failures, but spinning up a WMI query per
+// new process costs far more than the field is worth.
+#[cfg(feature = "process-info")]
+pub fn get_process_command_line(pid: u32) -> io::Result<String> {
+    let process = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_VM_READ)?;
+
+    let info = query_basic_information(&process)?;
+
+    let parameters: usize =
+        read_process_memory(&process, info.peb_base_address + PROCESS_PARAMETERS_OFFSET)?;
+    let command_line: UnicodeString =
+        read_process_memory(&process, parameters + COMMAND_LINE_OFFSET)?;
+
+    let mut text = vec![0u16; command_line.length as usize / 2];
+    read_process_bytes(
+        &process,
+        command_line.buffer,
+        text.as_mut_ptr() as *mut u8,
+        text.len() * 2,
+    )?;
+    Ok(String::from_utf16_lossy(&text))
+}
+
+// The PID of the process that created this one. The parent may have exited
+// since, and its PID been reused by an unrelated process; compare start times
+// to tell.
+#[cfg(feature = "process-info")]
+pub fn get_parent_process_id(pid: u32) -> io::Result<u32> {
+    let process = ProcessHandle::open(pid, PROCESS_QUERY_LIMITED_INFORMATION)?;
+    let info = query_basic_information(&process)?;
+    Ok(info.inherited_from_unique_process_id as u32)
+}
+
+#[cfg(feature = "process-info")]
+fn query_basic_information(process: &ProcessHandle) -> io::Result<ProcessBasicInformation> {
+    let mut info: ProcessBasicInformation = unsafe { mem::zeroed() };
+    let status = unsafe {
+        NtQueryInformationProcess(
+            process.as_raw(),
+            0, // ProcessBasicInformation
+            &mut info as *mut _ as *mut _,
+            mem::size_of::<ProcessBasicInformation>() as ULONG,
+            ptr::null_mut(),
+        )
+    };
+    if status < 0 {
+        return Err(io::Error::other(format!(
+            "NtQueryInformationProcess failed with {:#x}",
+            status
+        )));
+    }
+
+    Ok(info)
+}
+
+#[cfg(feature = "process-info")]
+fn read_process_memory<T>(process: &ProcessHandle, address: usize) -> io::Result<T> {
+    let mut value = mem::MaybeUninit::<T>::uninit();
+    read_process_bytes(process, address, value.as_mut_ptr() as *mut u8, mem::size_of::<T>())?;
+    Ok(unsafe { value.assume_init() })
+}
+
+#[cfg(feature = "process-info")]
+fn read_process_bytes(
+    process: &ProcessHandle,
+    address: usize,
+    buffer: *mut u8,
+    len: usize,
+) -> io::Result<()> {
+    let mut read = 0;
+    let result = unsafe {
+        ReadProcessMemory(
+            process.as_raw(),
+            address as *const _,
+            buffer as *mut _,
+            len,
+            &mut read,
+        )
+    };
+    if result == 0 {
+        Err(io::Error::last_os_error())
+    } else if read != len {
+        Err(io::Error::from(io::ErrorKind::UnexpectedEof))
+    } else {
+        Ok(())
+    }
+}
+
+// A FILETIME used as a duration, in 100ns intervals.
+#[cfg(feature = "process-info")]
+fn filetime_to_duration(time: FILETIME) -> Duration {
+    let time = (time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64;
+    Duration::from_nanos(time * 100)
+}
+
+// FILETIMEs count 100ns intervals since 1601-01-01.
+pub fn filetime_to_system_time(time: FILETIME) -> SystemTime {
+    const UNIX_EPOCH_AS_FILETIME: u64 = 116_444_736_000_000_000;
+
+    let time = (time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64;
+    let since_unix_epoch = time.saturating_sub(UNIX_EPOCH_AS_FILETIME);
+    UNIX_EPOCH + Duration::from_nanos(since_unix_epoch * 100)
+}
+
+// Canonical form of an executable path for comparing against allowlists:
+// `\Device\HarddiskVolumeN\...` paths are mapped back to their drive letter
+// and 8.3 short names such as `PROGRA~1` are expanded. Falls back to the input
+// for any part that can't be resolved.
+pub fn canonicalize_exe_path(path: &Path) -> PathBuf {
+    let path = resolve_device_path(path).unwrap_or_else(|| path.to_path_buf());
+    get_long_path_name(&path).unwrap_or(path)
+}
+
+fn resolve_device_path(path: &Path) -> Option<PathBuf> {
+    let path_str = path.to_str()?;
+    if !path_str.starts_with("\\Device\\") {
+        return None;
+    }
+
+    for letter in b'A'..=b'Z' {
+        let drive = format!("{}:", letter as char);
+        let drive_wide: Vec<u16> = drive.encode_utf16().chain(Some(0)).collect();
+
+        let mut target = vec![0u16; 1024];
+        let len = unsafe {
+            QueryDosDeviceW(drive_wide.as_ptr(), target.as_mut_ptr(), target.len() as DWORD)
+        };
+        if len == 0 {
+            continue;
+        }
+
+        // The result is a list of null-terminated strings; the first one is
+        // the current mapping.
+        let end = target.iter().position(|&c| c == 0).unwrap_or(len as usize);
+        let device = String::from_utf16_lossy(&target[..end]);
+
+        if let Some(rest) = path_str.strip_prefix(device.as_str()) {
+            if rest.is_empty() || rest.starts_with('\\') {
+                return Some(PathBuf::from(format!("{}{}", drive, rest)));
+            }
+        }
+    }
+
+    None
+}
+
+fn get_long_path_name(path: &Path) -> Option<PathBuf> {
+    let path_wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+
+    let mut long_path = vec![0u16; MAX_PATH];
+    loop {
+        let len = unsafe {
+            GetLongPathNameW(
+                path_wide.as_ptr(),
+                long_path.as_mut_ptr(),
+                long_path.len() as DWORD,
+            )
+        };
+        if len == 0 {
+            return None;
+        }
+
+        // When the buffer is too small the return value is the size needed,
+        // including the null terminator.
+        if len as usize >= long_path.len() {
+            long_path.resize(len as usize, 0);
+            continue;
+        }
+
+        long_path.truncate(len as usize);
+        return Some(PathBuf::from(OsString::from_wide(&long_path)));
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    mem, process, ptr,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use winapi::{
+    shared::{
+        minwindef::{BOOL, DWORD, LPARAM, LRESULT, UINT, WPARAM},
+        windef::HWND,
+    },
+    um::{
+        libloaderapi::GetModuleHandleW,
+        processthreadsapi::ProcessIdToSessionId,
+        winuser::{
+            CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW,
+            GetProcessWindowStation, GetUserObjectInformationW, RegisterClassExW,
+            TranslateMessage, HWND_MESSAGE, MSG, UOI_FLAGS, USEROBJECTFLAGS,
+            WM_WTSSESSION_CHANGE, WNDCLASSEXW, WSF_VISIBLE,
+            WTS_CONSOLE_CONNECT, WTS_CONSOLE_DISCONNECT, WTS_REMOTE_CONNECT,
+            WTS_REMOTE_DISCONNECT, WTS_SESSION_LOCK, WTS_SESSION_LOGOFF, WTS_SESSION_LOGON,
+            WTS_SESSION_UNLOCK,
+        },
+    },
+};
+
+// From WtsApi32.h, which winapi's `wtsapi32` doesn't cover.
+const NOTIFY_FOR_THIS_SESSION: DWORD = 0;
+
+#[link(name = "wtsapi32")]
+extern "system" {
+    fn WTSRegisterSessionNotification(hWnd: HWND, dwFlags: DWORD) -> BOOL;
+}
+
+// The `sessionChange` subtype of a `WM_WTSSESSION_CHANGE` notification for
+// this session.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionChange {
+    ConsoleConnect,
+    ConsoleDisconnect,
+    RemoteConnect,
+    RemoteDisconnect,
+    Logon,
+    Logoff,
+    Lock,
+    Unlock,
+}
+
+impl SessionChange {
+    fn from_code(code: WPARAM) -> Option<Self> {
+        match code {
+            WTS_CONSOLE_CONNECT => Some(Self::ConsoleConnect),
+            WTS_CONSOLE_DISCONNECT => Some(Self::ConsoleDisconnect),
+            WTS_REMOTE_CONNECT => Some(Self::RemoteConnect),
+            WTS_REMOTE_DISCONNECT => Some(Self::RemoteDisconnect),
+            WTS_SESSION_LOGON => Some(Self::Logon),
+            WTS_SESSION_LOGOFF => Some(Self::Logoff),
+            WTS_SESSION_LOCK => Some(Self::Lock),
+            WTS_SESSION_UNLOCK => Some(Self::Unlock),
+            _ => None,
+        }
+    }
+}
+
+// Whether the process can see an interactive desktop, which foreground events
+// need. Services run in session 0, and a process can also sit on a
+// non-interactive window station in any session; either way there is no
+// foreground window to follow and hooks install but never fire. Checked once,
+// since a process can't move between sessions or window stations.
+pub fn has_interactive_desktop() -> bool {
+    static INTERACTIVE: OnceCell<bool> = OnceCell::new();
+    *INTERACTIVE.get_or_init(|| {
+        let mut session_id = 0;
+        if unsafe { ProcessIdToSessionId(process::id(), &mut session_id) } != 0 && session_id == 0
+        {
+            return false;
+        }
+
+        let station = unsafe { GetProcessWindowStation() };
+        if station.is_null() {
+            return false;
+        }
+        let mut flags: USEROBJECTFLAGS = unsafe { mem::zeroed() };
+        let result = unsafe {
+            GetUserObjectInformationW(
+                station as _,
+                UOI_FLAGS as _,
+                &mut flags as *mut USEROBJECTFLAGS as _,
+                mem::size_of::<USEROBJECTFLAGS>() as u32,
+                ptr::null_mut(),
+            )
+        };
+        // Assume interactive if the flags can't be read, rather than refusing
+        // to start on a desktop that works.
+        result == 0 || flags.dwFlags & WSF_VISIBLE != 0
+    })
+}
+
+type Subscribers = Mutex<Vec<UnboundedSender<SessionChange>>>;
+
+// Session notifications are delivered as window messages, so one dedicated
+// thread owns a hidden message-only window and pumps its messages for the
+// life of the process, forwarding each change to every subscribed listener.
+fn subscribers() -> Option<&'static Subscribers> {
+    static SUBSCRIBERS: OnceCell<Option<Subscribers>> = OnceCell::new();
+
+    SUBSCRIBERS
+        .get_or_init(|| {
+            let (ready_tx, ready_rx) = mpsc::channel();
+
+            thread::Builder::new()
+                .name("win-foreground-listener-session".into())
+                .spawn(move || {
+                    let window = match create_window() {
+                        Some(window) => window,
+                        None => {
+                            let _ = ready_tx.send(false);
+                            return;
+                        }
+                    };
+                    let registered =
+                        unsafe { WTSRegisterSessionNotification(window, NOTIFY_FOR_THIS_SESSION) };
+                    let _ = ready_tx.send(registered != 0);
+                    if registered == 0 {
+                        return;
+                    }
+
+                    let mut msg: MSG = unsafe { mem::zeroed() };
+                    while unsafe { GetMessageW(&mut msg, ptr::null_mut(), 0, 0) } > 0 {
+                        unsafe {
+                            TranslateMessage(&msg);
+                            DispatchMessageW(&msg);
+                        }
+                    }
+                })
+                .ok()?;
+
+            match ready_rx.recv() {
+                Ok(true) => Some(Mutex::new(Vec::new())),
+                _ => None,
+            }
+        })
+        .as_ref()
+}
+
+fn create_window() -> Option<HWND> {
+    let class_name: Vec<u16> = "WinForegroundListenerSession\0".encode_utf16().collect();
+    let instance = unsafe { GetModuleHandleW(ptr::null()) };
+
+    let mut class: WNDCLASSEXW = unsafe { mem::zeroed() };
+    class.cbSize = mem::size_of::<WNDCLASSEXW>() as UINT;
+    class.lpfnWndProc = Some(window_proc);
+    class.hInstance = instance;
+    class.lpszClassName = class_name.as_ptr();
+    if unsafe { RegisterClassExW(&class) } == 0 {
+        return None;
+    }
+
+    let window = unsafe {
+        CreateWindowExW(
+            0,
+            class_name.as_ptr(),
+            ptr::null(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            HWND_MESSAGE,
+            ptr::null_mut(),
+            instance,
+            ptr::null_mut(),
+        )
+    };
+    Some(window).filter(|window| !window.is_null())
+}
+
+unsafe extern "system" fn window_proc(
+    window: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg == WM_WTSSESSION_CHANGE {
+        if let (Some(change), Some(subscribers)) = (SessionChange::from_code(wparam), subscribers())
+        {
+            // Listeners that have stopped are dropped here.
+            subscribers
+                .lock()
+                .unwrap()
+                .retain(|subscriber| subscriber.send(change).is_ok());
+        }
+        return 0;
+    }
+
+    DefWindowProcW(window, msg, wparam, lparam)
+}
+
+// Receive this session's changes until the receiver is dropped. `None` if
+// session notifications aren't available.
+pub fn subscribe() -> Option<UnboundedReceiver<SessionChange>> {
+    let (tx, rx) = unbounded_channel();
+    subscribers()?.lock().unwrap().push(tx);
+    Some(rx)
+}
@@ -0,0 +1,300 @@
+// The COM declarations below keep the Windows SDK's names.
+#![allow(non_snake_case, non_upper_case_globals)]
+
+use std::{
+    collections::HashSet,
+    env,
+    ffi::{c_void, OsStr, OsString},
+    fs,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    ptr, slice,
+    time::{Duration, Instant},
+};
+
+use winapi::{
+    shared::{
+        guiddef::{GUID, REFIID},
+        minwindef::{BOOL, MAX_PATH},
+        windef::HWND,
+        winerror::{HRESULT, SUCCEEDED},
+        wtypes::{PROPERTYKEY, VT_LPWSTR},
+        wtypesbase::CLSCTX_INPROC_SERVER,
+    },
+    um::{
+        combaseapi::{CoCreateInstance, PropVariantClear},
+        objidl::IPersistFile,
+        propidl::PROPVARIANT,
+        propsys::IPropertyStore,
+        shobjidl_core::{IShellLinkW, ShellLink as ShellLinkClass},
+        unknwnbase::{IUnknown, IUnknownVtbl},
+        winnt::LPWSTR,
+    },
+    Class, Interface, DEFINE_GUID, RIDL,
+};
+
+use crate::com::{ComGuard, ComPtr};
+use crate::{process, window};
+
+#[link(name = "shell32")]
+extern "system" {
+    fn SHGetPropertyStoreForWindow(hwnd: HWND, riid: REFIID, ppv: *mut *mut c_void) -> HRESULT;
+}
+
+// IVirtualDesktopManager from shobjidl_core.h, which winapi doesn't have.
+DEFINE_GUID! {CLSID_VirtualDesktopManager,
+0xaa509086, 0x5ca9, 0x4c25, 0x8f, 0x95, 0x58, 0x9d, 0x3c, 0x07, 0xb4, 0x8a}
+
+RIDL! {#[uuid(0xa5cd92ff, 0x29be, 0x454c, 0x8d, 0x04, 0xd8, 0x28, 0x79, 0xfb, 0x3f, 0x1b)]
+interface IVirtualDesktopManager(IVirtualDesktopManagerVtbl): IUnknown(IUnknownVtbl) {
+    fn IsWindowOnCurrentVirtualDesktop(
+        topLevelWindow: HWND,
+        onCurrentDesktop: *mut BOOL,
+    ) -> HRESULT,
+    fn GetWindowDesktopId(
+        topLevelWindow: HWND,
+        desktopId: *mut GUID,
+    ) -> HRESULT,
+}}
+
+// PKEY_AppUserModel_ID from propkey.h, which winapi doesn't define.
+const PKEY_APP_USER_MODEL_ID: PROPERTYKEY = PROPERTYKEY {
+    fmtid: GUID {
+        Data1: 0x9F4C_2855,
+        Data2: 0x9F79,
+        Data3: 0x4B39,
+        Data4: [0xA8, 0xD0, 0xE1, 0xD4, 0x2D, 0xE1, 0xD5, 0xF3],
+    },
+    pid: 5,
+};
+
+// How long the pinned-items snapshot is reused before re-reading the folder.
+const PINNED_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+// Read a string property, returning `None` if it is missing or not a string.
+fn get_string_property(store: &IPropertyStore, key: &PROPERTYKEY) -> Option<String> {
+    let mut value: PROPVARIANT = unsafe { std::mem::zeroed() };
+    if !SUCCEEDED(unsafe { store.GetValue(key, &mut value) }) {
+        return None;
+    }
+
+    let result = unsafe {
+        if value.vt == VT_LPWSTR as u16 {
+            from_wide_ptr(*value.data.pwszVal())
+        } else {
+            None
+        }
+    };
+
+    unsafe { PropVariantClear(&mut value) };
+    result
+}
+
+unsafe fn from_wide_ptr(ptr: LPWSTR) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+
+    let len = (0..).take_while(|&i| *ptr.offset(i) != 0).count();
+    let text = String::from_utf16_lossy(slice::from_raw_parts(ptr, len));
+    Some(text).filter(|text| !text.is_empty())
+}
+
+// The explicit AppUserModelID the window (or its process) has set, if any.
+pub fn get_window_app_id(window: HWND) -> Option<String> {
+    let _com = ComGuard::new();
+
+    let mut store = ptr::null_mut();
+    let hr = unsafe {
+        SHGetPropertyStoreForWindow(window, &IPropertyStore::uuidof(), &mut store)
+    };
+    if !SUCCEEDED(hr) || store.is_null() {
+        return None;
+    }
+
+    let store = unsafe { ComPtr::from_raw(store as *mut IPropertyStore) };
+    get_string_property(store.as_ref(), &PKEY_APP_USER_MODEL_ID)
+}
+
+// The id of the virtual desktop the window is on, as a braced GUID string.
+// `None` if the virtual desktop manager isn't available (before Windows 10,
+// or in some sessions without Explorer) or the window isn't on a desktop.
+pub fn get_window_desktop_id(window: HWND) -> Option<String> {
+    let _com = ComGuard::new();
+
+    let mut manager = ptr::null_mut();
+    let hr = unsafe {
+        CoCreateInstance(
+            &CLSID_VirtualDesktopManager,
+            ptr::null_mut(),
+            CLSCTX_INPROC_SERVER,
+            &IVirtualDesktopManager::uuidof(),
+            &mut manager,
+        )
+    };
+    if !SUCCEEDED(hr) || manager.is_null() {
+        return None;
+    }
+
+    let manager = unsafe { ComPtr::from_raw(manager as *mut IVirtualDesktopManager) };
+    let mut id: GUID = unsafe { std::mem::zeroed() };
+    if !SUCCEEDED(unsafe { manager.as_ref().GetWindowDesktopId(window, &mut id) }) {
+        return None;
+    }
+
+    // Windows that aren't on any desktop report the null GUID.
+    if id.Data1 == 0 && id.Data2 == 0 && id.Data3 == 0 && id.Data4 == [0; 8] {
+        return None;
+    }
+
+    Some(format_guid(&id))
+}
+
+fn format_guid(id: &GUID) -> String {
+    format!(
+        "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+        id.Data1,
+        id.Data2,
+        id.Data3,
+        id.Data4[0],
+        id.Data4[1],
+        id.Data4[2],
+        id.Data4[3],
+        id.Data4[4],
+        id.Data4[5],
+        id.Data4[6],
+        id.Data4[7],
+    )
+}
+
+// A snapshot of the shortcuts pinned to the taskbar, identified by the
+// AppUserModelID they set explicitly or otherwise by their target path.
+pub struct PinnedItems {
+    app_ids: HashSet<String>,
+    targets: HashSet<String>,
+    loaded_at: Option<Instant>,
+}
+
+impl PinnedItems {
+    pub fn new() -> Self {
+        Self {
+            app_ids: HashSet::new(),
+            targets: HashSet::new(),
+            loaded_at: None,
+        }
+    }
+
+    // Best-effort: `None` when the pinned folder or the window's identity
+    // can't be read.
+    pub fn is_pinned(&mut self, window: HWND) -> Option<bool> {
+        self.refresh()?;
+
+        if let Some(app_id) = get_window_app_id(window) {
+            if self.app_ids.contains(&app_id.to_lowercase()) {
+                return Some(true);
+            }
+        }
+
+        let pid = window::get_window_process_id(window).ok()?;
+        let path = process::get_process_image_path(pid).ok()?;
+        Some(self.targets.contains(&normalize_path(&path)))
+    }
+
+    fn refresh(&mut self) -> Option<()> {
+        match self.loaded_at {
+            Some(loaded_at) if loaded_at.elapsed() < PINNED_REFRESH_INTERVAL => return Some(()),
+            _ => (),
+        }
+
+        let dir = pinned_folder()?;
+        let entries = fs::read_dir(dir).ok()?;
+        let _com = ComGuard::new();
+
+        self.app_ids.clear();
+        self.targets.clear();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension() != Some(OsStr::new("lnk")) {
+                continue;
+            }
+
+            if let Some(link) = ShellLink::load(&path) {
+                if let Some(app_id) = link.app_id() {
+                    self.app_ids.insert(app_id.to_lowercase());
+                }
+                if let Some(target) = link.target() {
+                    self.targets.insert(normalize_path(&target));
+                }
+            }
+        }
+
+        self.loaded_at = Some(Instant::now());
+        Some(())
+    }
+}
+
+fn pinned_folder() -> Option<PathBuf> {
+    let app_data = env::var_os("APPDATA")?;
+    Some(
+        Path::new(&app_data)
+            .join("Microsoft\\Internet Explorer\\Quick Launch\\User Pinned\\TaskBar"),
+    )
+}
+
+fn normalize_path(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+// A `.lnk` shortcut loaded through `IShellLinkW`. COM must be initialized.
+struct ShellLink(ComPtr<IShellLinkW>);
+
+impl ShellLink {
+    fn load(path: &Path) -> Option<Self> {
+        let mut link = ptr::null_mut();
+        let hr = unsafe {
+            CoCreateInstance(
+                &ShellLinkClass::uuidof(),
+                ptr::null_mut(),
+                CLSCTX_INPROC_SERVER,
+                &IShellLinkW::uuidof(),
+                &mut link,
+            )
+        };
+        if !SUCCEEDED(hr) || link.is_null() {
+            return None;
+        }
+
+        let link = unsafe { ComPtr::from_raw(link as *mut IShellLinkW) };
+        let file = link.cast::<IPersistFile>()?;
+        let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        if !SUCCEEDED(unsafe { file.as_ref().Load(path.as_ptr(), 0) }) {
+            return None;
+        }
+
+        Some(Self(link))
+    }
+
+    fn app_id(&self) -> Option<String> {
+        let store = self.0.cast::<IPropertyStore>()?;
+        get_string_property(store.as_ref(), &PKEY_APP_USER_MODEL_ID)
+    }
+
+    fn target(&self) -> Option<PathBuf> {
+        let mut path = vec![0u16; MAX_PATH];
+        let hr = unsafe {
+            self.0.as_ref().GetPath(
+                path.as_mut_ptr(),
+                path.len() as i32,
+                ptr::null_mut(),
+                0,
+            )
+        };
+        if !SUCCEEDED(hr) {
+            return None;
+        }
+
+        let len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+        path.truncate(len);
+        Some(PathBuf::from(OsString::from_wide(&path))).filter(|path| !path.as_os_str().is_empty())
+    }
+}
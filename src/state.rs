@@ -0,0 +1,90 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::diagnostics::DiagnosticSession;
+use crate::event::ForegroundEvent;
+use crate::options::{StartOptions, UsageKey};
+use crate::usage::UsageStats;
+
+// State shared between a listener's task and its JS-facing methods.
+//
+// A fresh state is created on every start, so a previous task that is still
+// winding down can't write into the new session's state.
+pub type SharedState = Arc<Mutex<ListenerState>>;
+
+#[derive(Default)]
+pub struct ListenerState {
+    history: VecDeque<ForegroundEvent>,
+    history_size: usize,
+    // The last event handed to the callback, of any type.
+    pub last: Option<ForegroundEvent>,
+    pub ignored_hwnds: HashSet<isize>,
+    // Present when any subscription has `usageStatsBy` set.
+    pub usage: Option<UsageStats>,
+    // The `usageStatsBy` `usage` was created for.
+    usage_stats_by: Option<UsageKey>,
+    // Present while a `startDiagnosticSession` runs.
+    pub diagnostics: Option<DiagnosticSession>,
+}
+
+impl ListenerState {
+    // The state for one start, shared by all of its subscriptions when
+    // started with `listenerStartMany`: the history is as long as the longest
+    // requested and the ignored set is the union of all of them.
+    pub fn new<'a>(options: impl IntoIterator<Item = &'a StartOptions>) -> SharedState {
+        let mut state = Self::default();
+        for options in options {
+            state.history_size = state.history_size.max(options.history_size);
+            if let Some(hwnds) = &options.ignore_hwnds {
+                state.ignored_hwnds.extend(hwnds);
+            }
+            if options.usage_stats_by.is_some() && state.usage.is_none() {
+                state.usage = Some(UsageStats::new());
+                state.usage_stats_by = options.usage_stats_by;
+            }
+        }
+        state.history = VecDeque::with_capacity(state.history_size);
+
+        Arc::new(Mutex::new(state))
+    }
+
+    // Apply `listenerReconfigure`d options. Shrinking the history drops the
+    // oldest entries. The ignored set is only replaced if the new options
+    // give one, so a `setIgnoredHwnds` set survives unrelated changes.
+    // Changing `usageStatsBy` starts the stats over, since the keys change
+    // meaning; turning it off drops them.
+    pub fn reconfigure(&mut self, options: &StartOptions) {
+        self.history_size = options.history_size;
+        self.history.truncate(self.history_size);
+        if let Some(hwnds) = &options.ignore_hwnds {
+            self.ignored_hwnds = hwnds.clone();
+        }
+        if options.usage_stats_by != self.usage_stats_by {
+            self.usage = options.usage_stats_by.map(|_| UsageStats::new());
+            self.usage_stats_by = options.usage_stats_by;
+        }
+    }
+
+    pub fn push_history(&mut self, event: &ForegroundEvent) {
+        if self.history_size == 0 {
+            return;
+        }
+
+        if self.history.len() == self.history_size {
+            self.history.pop_back();
+        }
+        self.history.push_front(event.clone());
+    }
+
+    // Recent foreground events, newest first.
+    pub fn history(&self) -> impl Iterator<Item = &ForegroundEvent> {
+        self.history.iter()
+    }
+}
+
+// Record into the running diagnostic session, if there is one.
+pub fn diagnose(state: &SharedState, record: impl FnOnce(&mut DiagnosticSession)) {
+    if let Some(session) = &mut state.lock().unwrap().diagnostics {
+        record(session);
+    }
+}
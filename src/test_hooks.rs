@@ -0,0 +1,90 @@
+// Hooks for the JavaScript tests in `test/` to reach paths they otherwise
+// couldn't. Only built with the `test-hooks` feature, which `npm test` turns
+// on; `index.js` doesn't expose them.
+
+use std::cell::Cell;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use neon::prelude::*;
+use once_cell::sync::Lazy;
+
+static FAIL_NEXT_RUNTIME_INIT: AtomicBool = AtomicBool::new(false);
+
+// Numbers each global runtime as it's created, starting at 1.
+static RUNTIME_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// The runtime of each listener task that is running, by generation.
+static TASK_RUNTIMES: Lazy<Mutex<Vec<u64>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+thread_local! {
+    // The generation of the runtime this thread belongs to, or 0.
+    static THREAD_RUNTIME: Cell<u64> = const { Cell::new(0) };
+}
+
+// The error the next runtime creation fails with, if a test asked for one.
+pub fn runtime_init_failure() -> Option<io::Error> {
+    if FAIL_NEXT_RUNTIME_INIT.swap(false, Ordering::SeqCst) {
+        Some(io::Error::other("failure injected by a test"))
+    } else {
+        None
+    }
+}
+
+pub fn next_runtime_generation() -> u64 {
+    RUNTIME_GENERATION.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+// Called as each of the runtime's threads starts.
+pub fn enter_runtime_thread(generation: u64) {
+    THREAD_RUNTIME.with(|runtime| runtime.set(generation));
+}
+
+// Lists a listener task in `testTaskRuntimes()` under the runtime it is
+// actually running on, until it's dropped.
+pub struct TaskRuntime(u64);
+
+impl TaskRuntime {
+    pub fn enter() -> Self {
+        let generation = THREAD_RUNTIME.with(Cell::get);
+        TASK_RUNTIMES.lock().unwrap().push(generation);
+        TaskRuntime(generation)
+    }
+}
+
+impl Drop for TaskRuntime {
+    fn drop(&mut self) {
+        let mut runtimes = TASK_RUNTIMES.lock().unwrap();
+        if let Some(i) = runtimes.iter().position(|&generation| generation == self.0) {
+            runtimes.swap_remove(i);
+        }
+    }
+}
+
+// `testFailNextRuntimeInit()`: make the next start that has to create the
+// runtime fail as if tokio couldn't.
+fn js_fail_next_runtime_init(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    FAIL_NEXT_RUNTIME_INIT.store(true, Ordering::SeqCst);
+    Ok(cx.undefined())
+}
+
+// `testTaskRuntimes()`: the runtime generation of every running listener
+// task, 0 for one that isn't on a global runtime thread.
+fn js_task_runtimes(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let runtimes = TASK_RUNTIMES.lock().unwrap().clone();
+    let array = JsArray::new(&mut cx, runtimes.len() as u32);
+    for (i, generation) in runtimes.into_iter().enumerate() {
+        let generation = cx.number(generation as f64);
+        array.set(&mut cx, i as u32, generation)?;
+    }
+
+    Ok(array)
+}
+
+pub fn export(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("testFailNextRuntimeInit", js_fail_next_runtime_init)?;
+    cx.export_function("testTaskRuntimes", js_task_runtimes)?;
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::event::{EventType, ForegroundEvent};
+
+// Leading+trailing throttle: the first event of a burst is delivered
+// immediately, later ones are held back, and the last of them is delivered
+// once no new event has arrived for the whole interval.
+pub struct Throttle {
+    interval: Duration,
+    deadline: Option<Instant>,
+    pending: Option<ForegroundEvent>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            deadline: None,
+            pending: None,
+        }
+    }
+
+    // Returns the event if it should be delivered right away.
+    pub fn push(&mut self, event: ForegroundEvent) -> Option<ForegroundEvent> {
+        let leading = self.deadline.is_none();
+        self.deadline = Some(Instant::now() + self.interval);
+
+        if leading {
+            Some(event)
+        } else {
+            self.pending = Some(event);
+            None
+        }
+    }
+
+    // When the current burst settles, if one is in progress.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // End the burst, returning its trailing event if one was held back.
+    pub fn expire(&mut self) -> Option<ForegroundEvent> {
+        self.deadline = None;
+        self.pending.take()
+    }
+}
+
+// Trailing debounce: holds the latest event and releases it once no newer
+// one has arrived for the whole interval.
+pub struct Debounce {
+    interval: Duration,
+    deadline: Option<Instant>,
+    pending: Option<ForegroundEvent>,
+}
+
+impl Debounce {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            deadline: None,
+            pending: None,
+        }
+    }
+
+    pub fn push(&mut self, event: ForegroundEvent) {
+        self.deadline = Some(Instant::now() + self.interval);
+        self.pending = Some(event);
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn expire(&mut self) -> Option<ForegroundEvent> {
+        self.deadline = None;
+        self.pending.take()
+    }
+}
+
+// A separate trailing debounce per kind of event, for
+// `foregroundDebounceMs` and `nameChangeDebounceMs`. Types that change the
+// same thing share a timer, so e.g. `foregroundLost` can't overtake the
+// `foreground` it follows. Types without a debounce pass straight through.
+pub struct TypedDebounce {
+    debounces: HashMap<EventType, Debounce>,
+}
+
+impl TypedDebounce {
+    // `None` if no type is debounced.
+    pub fn new(foreground: Option<Duration>, name_change: Option<Duration>) -> Option<Self> {
+        let mut debounces = HashMap::new();
+        if let Some(interval) = foreground {
+            debounces.insert(EventType::Foreground, Debounce::new(interval));
+        }
+        if let Some(interval) = name_change {
+            debounces.insert(EventType::NameChange, Debounce::new(interval));
+        }
+
+        Some(Self { debounces }).filter(|debounce| !debounce.debounces.is_empty())
+    }
+
+    // Returns the event if its type isn't debounced.
+    pub fn push(&mut self, event: ForegroundEvent) -> Option<ForegroundEvent> {
+        match self.debounces.get_mut(&group(event.event_type)) {
+            Some(debounce) => {
+                debounce.push(event);
+                None
+            }
+            None => Some(event),
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.debounces.values().filter_map(Debounce::deadline).min()
+    }
+
+    // Release the pending event of one timer that is due, if any.
+    pub fn expire_due(&mut self, now: Instant) -> Option<ForegroundEvent> {
+        self.debounces
+            .values_mut()
+            .find(|debounce| debounce.deadline().is_some_and(|deadline| deadline <= now))
+            .and_then(Debounce::expire)
+    }
+}
+
+// The type whose timer an event type shares.
+fn group(event_type: EventType) -> EventType {
+    match event_type {
+        EventType::FocusStolen | EventType::ForegroundLost => EventType::Foreground,
+        other => other,
+    }
+}
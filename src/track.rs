@@ -0,0 +1,69 @@
+use std::num::NonZeroU32;
+
+use winapi::shared::windef::HWND;
+use wineventhook::{raw_event, EventFilter};
+
+use crate::event::EventType;
+use crate::{handle, window};
+
+// Hooks for following a single window: foreground changes system-wide, so
+// losing the foreground is noticed too, and the window's own location, name
+// and destroy events scoped to its process.
+pub fn filters(window: HWND) -> Vec<EventFilter> {
+    let pid = window::get_window_process_id(window)
+        .ok()
+        .and_then(NonZeroU32::new);
+
+    let scoped = |event| {
+        let filter = EventFilter::default().event(event);
+        match pid {
+            Some(pid) => filter.process(pid),
+            None => filter,
+        }
+    };
+
+    vec![
+        EventFilter::default().event(raw_event::SYSTEM_FOREGROUND),
+        scoped(raw_event::OBJECT_LOCATIONCHANGE),
+        scoped(raw_event::OBJECT_NAMECHANGE),
+        scoped(raw_event::OBJECT_DESTROY),
+    ]
+}
+
+// Turns raw WinEvents into the typed events of a tracked window.
+pub struct WindowTracker {
+    hwnd: isize,
+    is_foreground: bool,
+}
+
+impl WindowTracker {
+    pub fn new(hwnd: isize) -> Self {
+        Self {
+            hwnd,
+            is_foreground: false,
+        }
+    }
+
+    pub fn hwnd(&self) -> HWND {
+        handle::to_hwnd(self.hwnd)
+    }
+
+    pub fn classify(&mut self, event_id: i32, window: HWND) -> Option<EventType> {
+        let is_target = handle::from_hwnd(window) == self.hwnd;
+
+        match event_id {
+            raw_event::SYSTEM_FOREGROUND if is_target => {
+                self.is_foreground = true;
+                Some(EventType::Foreground)
+            }
+            raw_event::SYSTEM_FOREGROUND if self.is_foreground => {
+                self.is_foreground = false;
+                Some(EventType::ForegroundLost)
+            }
+            raw_event::OBJECT_LOCATIONCHANGE if is_target => Some(EventType::LocationChange),
+            raw_event::OBJECT_NAMECHANGE if is_target => Some(EventType::NameChange),
+            raw_event::OBJECT_DESTROY if is_target => Some(EventType::Destroy),
+            _ => None,
+        }
+    }
+}
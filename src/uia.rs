@@ -0,0 +1,522 @@
+// The COM declarations below keep the Windows SDK's names.
+#![allow(non_camel_case_types, non_snake_case, clippy::upper_case_acronyms)]
+
+use std::{
+    ptr,
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use once_cell::sync::OnceCell;
+use winapi::{
+    ctypes::{c_int, c_void},
+    shared::{
+        guiddef::REFIID,
+        minwindef::BOOL,
+        windef::{HWND, POINT},
+        winerror::{HRESULT, SUCCEEDED},
+        wtypes::{BSTR, VT_BSTR, VT_I4},
+        wtypesbase::CLSCTX_INPROC_SERVER,
+    },
+    um::{
+        combaseapi::CoCreateInstance,
+        oaidl::{SAFEARRAY, VARIANT},
+        oleauto::VariantClear,
+        unknwnbase::{IUnknown, IUnknownVtbl},
+        winuser::FindWindowExW,
+    },
+    Class, Interface, RIDL,
+};
+use crate::com::{self, ComGuard, ComPtr};
+use crate::handle;
+
+// The parts of UIAutomationClient.h this module uses, which winapi doesn't
+// have. Each vtable is declared up to the last method called here, in the
+// header's order; interfaces that are only passed through are `c_void`.
+type UIA_HWND = *mut c_void;
+type PROPERTYID = c_int;
+type CONTROLTYPEID = c_int;
+type PATTERNID = c_int;
+type TreeScope = u32;
+
+RIDL! {#[uuid(0xff48dba4, 0x60ef, 0x4201, 0xaa, 0x87, 0x54, 0x10, 0x3e, 0xef, 0x59, 0x4e)]
+class CUIAutomation;}
+
+RIDL! {#[uuid(0x352ffba8, 0x0973, 0x437c, 0xa6, 0x1f, 0xf6, 0x4c, 0xaf, 0xd8, 0x1d, 0xf9)]
+interface IUIAutomationCondition(IUIAutomationConditionVtbl): IUnknown(IUnknownVtbl) {}}
+
+RIDL! {#[uuid(0x14314595, 0xb4bc, 0x4055, 0x95, 0xf2, 0x58, 0xf2, 0xe4, 0x2c, 0x98, 0x55)]
+interface IUIAutomationElementArray(IUIAutomationElementArrayVtbl): IUnknown(IUnknownVtbl) {
+    fn get_Length(
+        length: *mut c_int,
+    ) -> HRESULT,
+    fn GetElement(
+        index: c_int,
+        element: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0xd22108aa, 0x8ac5, 0x49a5, 0x83, 0x7b, 0x37, 0xbb, 0xb3, 0xd7, 0x59, 0x1e)]
+interface IUIAutomationElement(IUIAutomationElementVtbl): IUnknown(IUnknownVtbl) {
+    fn SetFocus() -> HRESULT,
+    fn GetRuntimeId(
+        runtimeId: *mut *mut SAFEARRAY,
+    ) -> HRESULT,
+    fn FindFirst(
+        scope: TreeScope,
+        condition: *mut IUIAutomationCondition,
+        found: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn FindAll(
+        scope: TreeScope,
+        condition: *mut IUIAutomationCondition,
+        found: *mut *mut IUIAutomationElementArray,
+    ) -> HRESULT,
+    fn FindFirstBuildCache(
+        scope: TreeScope,
+        condition: *mut IUIAutomationCondition,
+        cacheRequest: *mut c_void,
+        found: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn FindAllBuildCache(
+        scope: TreeScope,
+        condition: *mut IUIAutomationCondition,
+        cacheRequest: *mut c_void,
+        found: *mut *mut IUIAutomationElementArray,
+    ) -> HRESULT,
+    fn BuildUpdatedCache(
+        cacheRequest: *mut c_void,
+        updatedElement: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn GetCurrentPropertyValue(
+        propertyId: PROPERTYID,
+        retVal: *mut VARIANT,
+    ) -> HRESULT,
+    fn GetCurrentPropertyValueEx(
+        propertyId: PROPERTYID,
+        ignoreDefaultValue: BOOL,
+        retVal: *mut VARIANT,
+    ) -> HRESULT,
+    fn GetCachedPropertyValue(
+        propertyId: PROPERTYID,
+        retVal: *mut VARIANT,
+    ) -> HRESULT,
+    fn GetCachedPropertyValueEx(
+        propertyId: PROPERTYID,
+        ignoreDefaultValue: BOOL,
+        retVal: *mut VARIANT,
+    ) -> HRESULT,
+    fn GetCurrentPatternAs(
+        patternId: PATTERNID,
+        riid: REFIID,
+        patternObject: *mut *mut c_void,
+    ) -> HRESULT,
+    fn GetCachedPatternAs(
+        patternId: PATTERNID,
+        riid: REFIID,
+        patternObject: *mut *mut c_void,
+    ) -> HRESULT,
+    fn GetCurrentPattern(
+        patternId: PATTERNID,
+        patternObject: *mut *mut IUnknown,
+    ) -> HRESULT,
+    fn GetCachedPattern(
+        patternId: PATTERNID,
+        patternObject: *mut *mut IUnknown,
+    ) -> HRESULT,
+    fn GetCachedParent(
+        parent: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn GetCachedChildren(
+        children: *mut *mut IUIAutomationElementArray,
+    ) -> HRESULT,
+    fn get_CurrentProcessId(
+        retVal: *mut c_int,
+    ) -> HRESULT,
+    fn get_CurrentControlType(
+        retVal: *mut CONTROLTYPEID,
+    ) -> HRESULT,
+    fn get_CurrentLocalizedControlType(
+        retVal: *mut BSTR,
+    ) -> HRESULT,
+    fn get_CurrentName(
+        retVal: *mut BSTR,
+    ) -> HRESULT,
+    fn get_CurrentAcceleratorKey(
+        retVal: *mut BSTR,
+    ) -> HRESULT,
+    fn get_CurrentAccessKey(
+        retVal: *mut BSTR,
+    ) -> HRESULT,
+    fn get_CurrentHasKeyboardFocus(
+        retVal: *mut BOOL,
+    ) -> HRESULT,
+    fn get_CurrentIsKeyboardFocusable(
+        retVal: *mut BOOL,
+    ) -> HRESULT,
+    fn get_CurrentIsEnabled(
+        retVal: *mut BOOL,
+    ) -> HRESULT,
+    fn get_CurrentAutomationId(
+        retVal: *mut BSTR,
+    ) -> HRESULT,
+    fn get_CurrentClassName(
+        retVal: *mut BSTR,
+    ) -> HRESULT,
+}}
+
+RIDL! {#[uuid(0x30cbe57d, 0xd9d0, 0x452a, 0xab, 0x13, 0x7a, 0xc5, 0xac, 0x48, 0x25, 0xee)]
+interface IUIAutomation(IUIAutomationVtbl): IUnknown(IUnknownVtbl) {
+    fn CompareElements(
+        el1: *mut IUIAutomationElement,
+        el2: *mut IUIAutomationElement,
+        areSame: *mut BOOL,
+    ) -> HRESULT,
+    fn CompareRuntimeIds(
+        runtimeId1: *mut SAFEARRAY,
+        runtimeId2: *mut SAFEARRAY,
+        areSame: *mut BOOL,
+    ) -> HRESULT,
+    fn GetRootElement(
+        root: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn ElementFromHandle(
+        hwnd: UIA_HWND,
+        element: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn ElementFromPoint(
+        pt: POINT,
+        element: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn GetFocusedElement(
+        element: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn GetRootElementBuildCache(
+        cacheRequest: *mut c_void,
+        root: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn ElementFromHandleBuildCache(
+        hwnd: UIA_HWND,
+        cacheRequest: *mut c_void,
+        element: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn ElementFromPointBuildCache(
+        pt: POINT,
+        cacheRequest: *mut c_void,
+        element: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn GetFocusedElementBuildCache(
+        cacheRequest: *mut c_void,
+        element: *mut *mut IUIAutomationElement,
+    ) -> HRESULT,
+    fn CreateTreeWalker(
+        pCondition: *mut IUIAutomationCondition,
+        walker: *mut *mut c_void,
+    ) -> HRESULT,
+    fn get_ControlViewWalker(
+        walker: *mut *mut c_void,
+    ) -> HRESULT,
+    fn get_ContentViewWalker(
+        walker: *mut *mut c_void,
+    ) -> HRESULT,
+    fn get_RawViewWalker(
+        walker: *mut *mut c_void,
+    ) -> HRESULT,
+    fn get_RawViewCondition(
+        condition: *mut *mut IUIAutomationCondition,
+    ) -> HRESULT,
+    fn get_ControlViewCondition(
+        condition: *mut *mut IUIAutomationCondition,
+    ) -> HRESULT,
+    fn get_ContentViewCondition(
+        condition: *mut *mut IUIAutomationCondition,
+    ) -> HRESULT,
+    fn CreateCacheRequest(
+        cacheRequest: *mut *mut c_void,
+    ) -> HRESULT,
+    fn CreateTrueCondition(
+        newCondition: *mut *mut IUIAutomationCondition,
+    ) -> HRESULT,
+    fn CreateFalseCondition(
+        newCondition: *mut *mut IUIAutomationCondition,
+    ) -> HRESULT,
+    fn CreatePropertyCondition(
+        propertyId: PROPERTYID,
+        value: VARIANT,
+        newCondition: *mut *mut IUIAutomationCondition,
+    ) -> HRESULT,
+}}
+
+// How long a UI Automation query may take before its result is given up on.
+pub const TIMEOUT: Duration = Duration::from_millis(250);
+
+type Job = Box<dyn FnOnce(&IUIAutomation) + Send>;
+
+// UI Automation calls run on one dedicated thread that owns the
+// `IUIAutomation` instance, so the listener task never blocks on a
+// misbehaving provider for longer than the timeout. A query that times out
+// still finishes on the worker before the next one starts.
+fn worker() -> Option<&'static Mutex<mpsc::Sender<Job>>> {
+    static WORKER: OnceCell<Option<Mutex<mpsc::Sender<Job>>>> = OnceCell::new();
+
+    WORKER
+        .get_or_init(|| {
+            let (job_tx, job_rx) = mpsc::channel::<Job>();
+            let (ready_tx, ready_rx) = mpsc::channel();
+
+            thread::Builder::new()
+                .name("win-foreground-listener-uia".into())
+                .spawn(move || {
+                    let _com = ComGuard::new();
+                    let automation = match create_automation() {
+                        Some(automation) => automation,
+                        None => {
+                            let _ = ready_tx.send(false);
+                            return;
+                        }
+                    };
+                    let _ = ready_tx.send(true);
+
+                    for job in job_rx {
+                        job(automation.as_ref());
+                    }
+                })
+                .ok()?;
+
+            match ready_rx.recv() {
+                Ok(true) => Some(Mutex::new(job_tx)),
+                _ => None,
+            }
+        })
+        .as_ref()
+}
+
+fn create_automation() -> Option<ComPtr<IUIAutomation>> {
+    let mut automation = ptr::null_mut();
+    let hr = unsafe {
+        CoCreateInstance(
+            &CUIAutomation::uuidof(),
+            ptr::null_mut(),
+            CLSCTX_INPROC_SERVER,
+            &IUIAutomation::uuidof(),
+            &mut automation,
+        )
+    };
+    if SUCCEEDED(hr) && !automation.is_null() {
+        Some(unsafe { ComPtr::from_raw(automation as *mut IUIAutomation) })
+    } else {
+        None
+    }
+}
+
+// Run a query on the UI Automation thread, giving up after `timeout`.
+// `None` if UI Automation isn't available, the query failed or timed out.
+pub fn query<T, F>(timeout: Duration, f: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&IUIAutomation) -> Option<T> + Send + 'static,
+{
+    let (result_tx, result_rx) = mpsc::channel();
+    let job: Job = Box::new(move |automation| {
+        let _ = result_tx.send(f(automation));
+    });
+
+    worker()?.lock().unwrap().send(job).ok()?;
+    result_rx.recv_timeout(timeout).ok().flatten()
+}
+
+// The UI Automation name of the element that has keyboard focus.
+pub fn focused_element_name() -> Option<String> {
+    query(TIMEOUT, |automation| {
+        let mut element = ptr::null_mut();
+        let hr = unsafe { automation.GetFocusedElement(&mut element) };
+        if !SUCCEEDED(hr) || element.is_null() {
+            return None;
+        }
+        let element = unsafe { ComPtr::from_raw(element) };
+
+        element_name(element.as_ref())
+    })
+}
+
+fn element_from_handle(automation: &IUIAutomation, hwnd: isize) -> Option<ComPtr<IUIAutomationElement>> {
+    let mut element = ptr::null_mut();
+    let hr = unsafe { automation.ElementFromHandle(handle::to_hwnd(hwnd) as *mut _, &mut element) };
+    if !SUCCEEDED(hr) || element.is_null() {
+        return None;
+    }
+
+    Some(unsafe { ComPtr::from_raw(element) })
+}
+
+pub fn element_name(element: &IUIAutomationElement) -> Option<String> {
+    let mut name = ptr::null_mut();
+    if !SUCCEEDED(unsafe { element.get_CurrentName(&mut name) }) {
+        return None;
+    }
+
+    unsafe { com::take_bstr(name) }.filter(|name| !name.is_empty())
+}
+
+// `TreeScope_Descendants` and `UIA_ButtonControlTypeId` from UIAutomation.h.
+const TREE_SCOPE_DESCENDANTS: u32 = 0x4;
+const UIA_BUTTON_CONTROL_TYPE_ID: i32 = 50000;
+
+// The names of the taskbar's app buttons, left to right.
+//
+// Windows doesn't expose the taskbar's order, so this walks its UI Automation
+// tree, which is an implementation detail of the shell: Windows 10 keeps the
+// buttons under the `MSTaskListWClass` window, and Windows 11 draws them with
+// XAML under `Shell_TrayWnd` with `TaskListButton` in their class name. Other
+// versions, other shells and taskbar tweaking tools may yield nothing.
+pub fn taskbar_button_names() -> Option<Vec<String>> {
+    let tray = find_window(ptr::null_mut(), "Shell_TrayWnd")?;
+    let task_list = find_window(tray, "ReBarWindow32")
+        .and_then(|rebar| find_window(rebar, "MSTaskSwWClass"))
+        .and_then(|task_switch| find_window(task_switch, "MSTaskListWClass"));
+    let (root, is_xaml) = match task_list {
+        Some(task_list) => (handle::from_hwnd(task_list), false),
+        None => (handle::from_hwnd(tray), true),
+    };
+
+    query(TIMEOUT, move |automation| {
+        let element = element_from_handle(automation, root)?;
+
+        let mut condition = ptr::null_mut();
+        if !SUCCEEDED(unsafe { automation.CreateTrueCondition(&mut condition) }) {
+            return None;
+        }
+        let condition = unsafe { ComPtr::from_raw(condition) };
+
+        let mut found = ptr::null_mut();
+        let hr = unsafe {
+            element
+                .as_ref()
+                .FindAll(TREE_SCOPE_DESCENDANTS, condition.as_raw(), &mut found)
+        };
+        if !SUCCEEDED(hr) || found.is_null() {
+            return None;
+        }
+        let found = unsafe { ComPtr::from_raw(found) };
+
+        let mut len = 0;
+        if !SUCCEEDED(unsafe { found.as_ref().get_Length(&mut len) }) {
+            return None;
+        }
+
+        let mut names = Vec::new();
+        for i in 0..len {
+            let mut button = ptr::null_mut();
+            if !SUCCEEDED(unsafe { found.as_ref().GetElement(i, &mut button) }) || button.is_null()
+            {
+                continue;
+            }
+            let button = unsafe { ComPtr::from_raw(button) };
+            if is_task_button(button.as_ref(), is_xaml) {
+                names.push(element_name(button.as_ref()).unwrap_or_default());
+            }
+        }
+        Some(names)
+    })
+}
+
+fn is_task_button(element: &IUIAutomationElement, is_xaml: bool) -> bool {
+    let mut control_type = 0;
+    if !SUCCEEDED(unsafe { element.get_CurrentControlType(&mut control_type) })
+        || control_type != UIA_BUTTON_CONTROL_TYPE_ID
+    {
+        return false;
+    }
+    if !is_xaml {
+        return true;
+    }
+
+    let mut class_name = ptr::null_mut();
+    if !SUCCEEDED(unsafe { element.get_CurrentClassName(&mut class_name) }) {
+        return false;
+    }
+    unsafe { com::take_bstr(class_name) }.is_some_and(|name| name.contains("TaskListButton"))
+}
+
+fn find_window(parent: HWND, class_name: &str) -> Option<HWND> {
+    let class_name: Vec<u16> = class_name.encode_utf16().chain(Some(0)).collect();
+    let window =
+        unsafe { FindWindowExW(parent, ptr::null_mut(), class_name.as_ptr(), ptr::null()) };
+    Some(window).filter(|window| !window.is_null())
+}
+
+// `UIA_ControlTypePropertyId`, `UIA_ValueValuePropertyId` and
+// `UIA_EditControlTypeId` from UIAutomation.h.
+const UIA_CONTROL_TYPE_PROPERTY_ID: i32 = 30003;
+const UIA_VALUE_VALUE_PROPERTY_ID: i32 = 30045;
+const UIA_EDIT_CONTROL_TYPE_ID: i32 = 50004;
+
+// The value of the first edit control in the window, which in Chromium-based
+// browsers and Firefox is the address bar. Only meaningful for those; other
+// windows' first edit control could be anything.
+//
+// Both browsers expose their own UI to UI Automation without any setup, but
+// Chromium builds its accessibility tree lazily, so the first query for a
+// window can time out and return `None`. Reading another process's UI needs
+// no special permission, except that a non-elevated process can't read an
+// elevated browser's.
+pub fn address_bar_value(window: HWND) -> Option<String> {
+    let hwnd = handle::from_hwnd(window);
+
+    query(TIMEOUT, move |automation| {
+        let element = element_from_handle(automation, hwnd)?;
+
+        let mut control_type: VARIANT = unsafe { std::mem::zeroed() };
+        unsafe {
+            let variant = control_type.n1.n2_mut();
+            variant.vt = VT_I4 as u16;
+            *variant.n3.lVal_mut() = UIA_EDIT_CONTROL_TYPE_ID;
+        }
+        let mut condition = ptr::null_mut();
+        let hr = unsafe {
+            automation.CreatePropertyCondition(
+                UIA_CONTROL_TYPE_PROPERTY_ID,
+                control_type,
+                &mut condition,
+            )
+        };
+        if !SUCCEEDED(hr) || condition.is_null() {
+            return None;
+        }
+        let condition = unsafe { ComPtr::from_raw(condition) };
+
+        let mut edit = ptr::null_mut();
+        let hr = unsafe {
+            element
+                .as_ref()
+                .FindFirst(TREE_SCOPE_DESCENDANTS, condition.as_raw(), &mut edit)
+        };
+        if !SUCCEEDED(hr) || edit.is_null() {
+            return None;
+        }
+        let edit = unsafe { ComPtr::from_raw(edit) };
+
+        let mut value: VARIANT = unsafe { std::mem::zeroed() };
+        let hr = unsafe {
+            edit.as_ref()
+                .GetCurrentPropertyValue(UIA_VALUE_VALUE_PROPERTY_ID, &mut value)
+        };
+        if !SUCCEEDED(hr) {
+            return None;
+        }
+
+        unsafe {
+            let variant = value.n1.n2_mut();
+            if variant.vt == VT_BSTR as u16 {
+                // `take_bstr` frees the string, which is all clearing the
+                // variant would do.
+                com::take_bstr(*variant.n3.bstrVal())
+            } else {
+                VariantClear(&mut value);
+                None
+            }
+        }
+        .filter(|value| !value.is_empty())
+    })
+}
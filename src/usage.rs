@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+// Total foreground time per process or executable while a listener runs,
+// timed natively as foreground changes arrive rather than when JavaScript
+// gets around to handling them.
+pub struct UsageStats {
+    totals: HashMap<String, Entry>,
+    // What has the foreground now, and since when.
+    current: Option<(String, Instant)>,
+    // When the listener started or the totals were last reset.
+    observed_since: SystemTime,
+}
+
+#[derive(Default)]
+struct Entry {
+    total: Duration,
+    // The start time of the process a `usageStatsBy: "pid"` key belongs to.
+    process_start: Option<SystemTime>,
+    // When the PID was seen reused by another process, which is as close to
+    // the process's exit as we know.
+    ended: Option<SystemTime>,
+}
+
+// One key of `getUsageStats({ detailed: true })`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageDetail {
+    foreground_ms: u64,
+    // Since the process started, for PID keys.
+    lifetime_ms: Option<u64>,
+    // The fraction of the part of the process's lifetime the listener has
+    // observed that it was foreground, from 0 to 1. The listener (or the last
+    // reset) may have started long after the process did, and time before
+    // that can't be accounted for.
+    lifetime_share: Option<f64>,
+}
+
+impl UsageStats {
+    pub fn new() -> Self {
+        Self {
+            totals: HashMap::new(),
+            current: None,
+            observed_since: SystemTime::now(),
+        }
+    }
+
+    // Close the running segment and start one for `key`, or none for `None`,
+    // e.g. when the listener stops. `process_start` belongs to the process a
+    // PID key was read from: if the PID was last used by a process with a
+    // different start time, the earlier process's total moves to
+    // `pid-processStartTime`, as in `stableId`, and `key` starts again from
+    // zero.
+    pub fn switch_to(&mut self, key: Option<String>, process_start: Option<SystemTime>) {
+        let now = Instant::now();
+        if let Some((key, since)) = self.current.take() {
+            self.totals.entry(key).or_default().total += now - since;
+        }
+
+        if let (Some(key), Some(start)) = (&key, process_start) {
+            self.archive_if_reused(key, start);
+        }
+        self.current = key.map(|key| (key, now));
+    }
+
+    fn archive_if_reused(&mut self, key: &str, start: SystemTime) {
+        let entry = self.totals.entry(key.to_owned()).or_default();
+        match entry.process_start {
+            Some(previous) if previous != start => {
+                let mut previous_entry = std::mem::take(entry);
+                previous_entry.ended = Some(SystemTime::now());
+                entry.process_start = Some(start);
+                self.totals
+                    .insert(archived_key(key, previous), previous_entry);
+            }
+            Some(_) => {}
+            None => entry.process_start = Some(start),
+        }
+    }
+
+    // Totals in milliseconds, including the running segment so far.
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.totals()
+            .into_iter()
+            .map(|(key, (total, _))| (key, total.as_millis() as u64))
+            .collect()
+    }
+
+    // Totals with the share of each process's lifetime they make up.
+    pub fn detailed_snapshot(&self) -> BTreeMap<String, UsageDetail> {
+        let now = SystemTime::now();
+        self.totals()
+            .into_iter()
+            .map(|(key, (total, entry))| {
+                let lifetime = entry.and_then(|entry| {
+                    let start = entry.process_start?;
+                    let end = entry.ended.unwrap_or(now);
+                    let observed_from = start.max(self.observed_since);
+                    Some((
+                        end.duration_since(start).ok()?,
+                        end.duration_since(observed_from).ok()?,
+                    ))
+                });
+                let detail = UsageDetail {
+                    foreground_ms: total.as_millis() as u64,
+                    lifetime_ms: lifetime.map(|(lifetime, _)| lifetime.as_millis() as u64),
+                    lifetime_share: lifetime.map(|(_, observed)| {
+                        if observed.is_zero() {
+                            0.0
+                        } else {
+                            (total.as_secs_f64() / observed.as_secs_f64()).min(1.0)
+                        }
+                    }),
+                };
+                (key, detail)
+            })
+            .collect()
+    }
+
+    fn totals(&self) -> BTreeMap<String, (Duration, Option<&Entry>)> {
+        let mut totals: BTreeMap<_, _> = self
+            .totals
+            .iter()
+            .map(|(key, entry)| (key.clone(), (entry.total, Some(entry))))
+            .collect();
+        if let Some((key, since)) = &self.current {
+            totals.entry(key.clone()).or_insert((Duration::ZERO, None)).0 += since.elapsed();
+        }
+        totals
+    }
+
+    // Forget the totals. The running segment restarts from now, and keeps
+    // the start time of its process.
+    pub fn reset(&mut self) {
+        let current_start = self.current.as_ref().and_then(|(key, _)| {
+            self.totals
+                .get(key)
+                .and_then(|entry| entry.process_start)
+        });
+        self.totals.clear();
+        if let Some((key, since)) = &mut self.current {
+            *since = Instant::now();
+            if let Some(start) = current_start {
+                self.totals.entry(key.clone()).or_default().process_start = Some(start);
+            }
+        }
+        self.observed_since = SystemTime::now();
+    }
+}
+
+fn archived_key(key: &str, start: SystemTime) -> String {
+    let start = start
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |start| start.as_micros());
+    format!("{}-{}", key, start)
+}
@@ -0,0 +1,58 @@
+use neon::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+
+// Convert any serializable value into the equivalent JS value.
+pub fn to_js<'a, C: Context<'a>, T: Serialize>(cx: &mut C, value: &T) -> JsResult<'a, JsValue> {
+    let value = serde_json::to_value(value).or_else(|err| cx.throw_error(err.to_string()))?;
+
+    json_to_js(cx, &value)
+}
+
+// Set `key` on `obj` to `value` as a BigInt. Neon has no BigInt type yet, so
+// this goes through the global `BigInt` function.
+pub fn set_bigint<'a, C: Context<'a>>(
+    cx: &mut C,
+    obj: Handle<'a, JsObject>,
+    key: &str,
+    value: i64,
+) -> NeonResult<()> {
+    let bigint = cx.global().get::<JsFunction, _, _>(cx, "BigInt")?;
+    let this = cx.undefined();
+    let arg = cx.string(value.to_string());
+    let value = bigint.call(cx, this, vec![arg.upcast()])?;
+    obj.set(cx, key, value)?;
+
+    Ok(())
+}
+
+fn json_to_js<'a, C: Context<'a>>(cx: &mut C, value: &Value) -> JsResult<'a, JsValue> {
+    Ok(match value {
+        Value::Null => cx.null().upcast(),
+        Value::Bool(value) => cx.boolean(*value).upcast(),
+        Value::Number(value) => cx.number(value.as_f64().unwrap_or(f64::NAN)).upcast(),
+        Value::String(value) => cx.string(value).upcast(),
+        Value::Array(values) => {
+            let array = JsArray::new(cx, values.len() as u32);
+            for (i, value) in values.iter().enumerate() {
+                let value = json_to_js(cx, value)?;
+                array.set(cx, i as u32, value)?;
+            }
+            array.upcast()
+        }
+        Value::Object(entries) => {
+            let obj = cx.empty_object();
+            for (key, value) in entries {
+                let value = json_to_js(cx, value)?;
+                obj.set(cx, key.as_str(), value)?;
+            }
+            obj.upcast()
+        }
+    })
+}
+
+// Encode a value as MessagePack, with structs as maps keyed by their
+// serialized field names so the keys match `to_js`.
+pub fn to_msgpack<T: Serialize>(value: &T) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    rmp_serde::to_vec_named(value)
+}
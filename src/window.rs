@@ -0,0 +1,638 @@
+use std::{collections::HashSet, io, mem, num::NonZeroUsize, process, ptr};
+
+use winapi::{
+    shared::{
+        minwindef::{BOOL, DWORD, LPARAM, TRUE},
+        windef::{HWND, RECT},
+        winerror::S_OK,
+    },
+    um::{
+        dwmapi::{DwmGetWindowAttribute, DWMWA_CLOAKED},
+        errhandlingapi::{GetLastError, SetLastError},
+        winuser::{
+            EnumWindows, GetAncestor, GetClassNameW, GetClipboardSequenceNumber, GetDesktopWindow,
+            GetForegroundWindow, GetLastActivePopup, GetLayeredWindowAttributes, GetPropW,
+            GetWindow, GetWindowLongPtrW, GetWindowPlacement, GetWindowRect, GetWindowTextLengthW,
+            GetWindowTextW, GetWindowThreadProcessId, InternalGetWindowText, IsIconic, IsWindow,
+            IsWindowEnabled, IsWindowVisible, GA_PARENT, GA_ROOTOWNER, GWL_EXSTYLE, GWL_STYLE,
+            GW_OWNER, LWA_ALPHA, WINDOWPLACEMENT, WS_EX_APPWINDOW, WS_EX_DLGMODALFRAME,
+            WS_EX_LAYERED, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_POPUP,
+        },
+    },
+};
+
+use crate::handle;
+
+// Several Win32 getters return 0 both on failure and for a legitimately empty
+// result (an empty title, no extended styles), and only set the last error in
+// the failure case. Those helpers clear the last error before the call and
+// only treat 0 as a failure if it was set again, so an untitled window reads
+// as an empty title rather than an error.
+
+pub fn get_window_text_length(window: HWND) -> io::Result<Option<NonZeroUsize>> {
+    unsafe { SetLastError(0) };
+    let result = unsafe { GetWindowTextLengthW(window) };
+    if result == 0 && unsafe { GetLastError() } != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(NonZeroUsize::new(result as usize))
+    }
+}
+
+pub fn get_window_text(window: HWND) -> io::Result<Option<String>> {
+    let text_len = if let Some(length) = get_window_text_length(window)? {
+        length.get()
+    } else {
+        return Ok(None);
+    };
+
+    // Zero-initialized rather than `with_capacity` + `set_len`, so there's no
+    // way to expose uninitialized memory however many code units are written.
+    let mut text = vec![0u16; text_len + 1]; // +1 for null terminator
+    unsafe { SetLastError(0) };
+    let result = unsafe { GetWindowTextW(window, text.as_mut_ptr(), text.len() as i32) };
+    if result == 0 && unsafe { GetLastError() } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // The title may have been cleared since the length was queried.
+    if result == 0 {
+        return Ok(None);
+    }
+
+    // Only the returned count is meaningful: the title may have shrunk since
+    // the length was queried, and if it grew it was truncated to fit.
+    text.truncate((result as usize).min(text_len));
+    Ok(Some(String::from_utf16_lossy(&text)))
+}
+
+// The title of any window, without risking a hang.
+//
+// For windows of other processes `GetWindowTextW` reads the title the system
+// keeps for the window and never waits on the target. For windows of our own
+// process it sends `WM_GETTEXT` to the owning thread instead, and that can
+// block for as long as the thread is busy, e.g. an Electron main thread that
+// is running JavaScript. Those are read with `InternalGetWindowText`, which
+// returns the kept title without sending anything, so a window of ours that
+// answers `WM_GETTEXT` with some other text reads as its kept title.
+pub fn read_window_text(window: HWND) -> io::Result<Option<String>> {
+    if get_window_process_id(window)? != process::id() {
+        return get_window_text(window);
+    }
+
+    // Nothing reports the kept title's length without sending a message, so
+    // the buffer grows until the title fits.
+    let mut text = vec![0u16; 256];
+    loop {
+        unsafe { SetLastError(0) };
+        let result = unsafe { InternalGetWindowText(window, text.as_mut_ptr(), text.len() as i32) };
+        if result == 0 && unsafe { GetLastError() } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Only a title that filled the buffer may have been cut short.
+        let len = result as usize;
+        if len + 1 < text.len() || text.len() >= MAX_TEXT_LEN {
+            text.truncate(len);
+            return Ok(Some(String::from_utf16_lossy(&text)).filter(|text| !text.is_empty()));
+        }
+        text.resize(text.len() * 2, 0);
+    }
+}
+
+// Where `read_window_text` stops growing its buffer and truncates instead.
+const MAX_TEXT_LEN: usize = 32 * 1024;
+
+// Class names can't be empty, so unlike the title getters a 0 return from
+// `GetClassNameW` is always a failure.
+pub fn get_class_name(window: HWND) -> io::Result<String> {
+    // Class names are limited to 256 characters.
+    let mut name = [0u16; 257];
+    let result = unsafe { GetClassNameW(window, name.as_mut_ptr(), name.len() as i32) };
+    if result == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(String::from_utf16_lossy(&name[..result as usize]))
+    }
+}
+
+// Whether the handle still identifies an existing window. A destroyed
+// window's handle may eventually be reused, so this can't prove it's the same
+// window, only that the handle isn't stale yet.
+pub fn is_window(window: HWND) -> bool {
+    unsafe { IsWindow(window) != 0 }
+}
+
+pub fn get_window_rect(window: HWND) -> io::Result<RECT> {
+    let mut rect: RECT = unsafe { mem::zeroed() };
+    if unsafe { GetWindowRect(window, &mut rect) } != 0 {
+        Ok(rect)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+pub fn get_window_placement(window: HWND) -> io::Result<WINDOWPLACEMENT> {
+    let mut placement: WINDOWPLACEMENT = unsafe { mem::zeroed() };
+    placement.length = mem::size_of::<WINDOWPLACEMENT>() as u32;
+    if unsafe { GetWindowPlacement(window, &mut placement) } != 0 {
+        Ok(placement)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+pub fn get_window_ex_style(window: HWND) -> io::Result<u32> {
+    unsafe { SetLastError(0) };
+    let result = unsafe { GetWindowLongPtrW(window, GWL_EXSTYLE) };
+    if result == 0 && unsafe { GetLastError() } != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as u32)
+    }
+}
+
+pub fn get_window_style(window: HWND) -> io::Result<u32> {
+    unsafe { SetLastError(0) };
+    let result = unsafe { GetWindowLongPtrW(window, GWL_STYLE) };
+    if result == 0 && unsafe { GetLastError() } != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result as u32)
+    }
+}
+
+pub fn is_topmost(window: HWND) -> io::Result<bool> {
+    Ok(get_window_ex_style(window)? & WS_EX_TOPMOST != 0)
+}
+
+pub fn is_layered(window: HWND) -> io::Result<bool> {
+    Ok(get_window_ex_style(window)? & WS_EX_LAYERED != 0)
+}
+
+// The window that currently has the foreground, or null, e.g. while the
+// foreground is being switched.
+pub fn get_foreground_window() -> HWND {
+    unsafe { GetForegroundWindow() }
+}
+
+// Whether a window of this process currently has the foreground.
+pub fn is_own_process_foreground() -> bool {
+    let window = get_foreground_window();
+    !window.is_null() && get_window_process_id(window).ok() == Some(process::id())
+}
+
+// Incremented by Windows whenever the clipboard's contents change. `None`
+// without clipboard access to the window station, when it reads as 0.
+pub fn get_clipboard_sequence_number() -> Option<u32> {
+    Some(unsafe { GetClipboardSequenceNumber() }).filter(|&sequence| sequence != 0)
+}
+
+// All top-level windows, in Z order from front to back.
+pub fn enum_windows() -> Vec<HWND> {
+    unsafe extern "system" fn callback(window: HWND, data: LPARAM) -> BOOL {
+        let windows = &mut *(data as *mut Vec<HWND>);
+        windows.push(window);
+        TRUE
+    }
+
+    let mut windows = Vec::new();
+    unsafe { EnumWindows(Some(callback), &mut windows as *mut Vec<HWND> as LPARAM) };
+    windows
+}
+
+pub fn is_visible(window: HWND) -> bool {
+    unsafe { IsWindowVisible(window) != 0 }
+}
+
+pub fn is_minimized(window: HWND) -> bool {
+    unsafe { IsIconic(window) != 0 }
+}
+
+// The taskbar's heuristic for a genuine application window: visible, and
+// either explicitly marked as an app window or an unowned non-tool window.
+pub fn is_app_window(window: HWND) -> io::Result<bool> {
+    if !is_visible(window) {
+        return Ok(false);
+    }
+
+    let ex_style = get_window_ex_style(window)?;
+    if ex_style & WS_EX_APPWINDOW != 0 {
+        return Ok(true);
+    }
+
+    let owner = unsafe { GetWindow(window, GW_OWNER) };
+    Ok(ex_style & WS_EX_TOOLWINDOW == 0 && owner.is_null())
+}
+
+// Whether DWM is hiding the window, as it does for windows on other virtual
+// desktops and suspended UWP apps, which are otherwise visible.
+pub fn is_cloaked(window: HWND) -> io::Result<bool> {
+    let mut cloaked: DWORD = 0;
+    let result = unsafe {
+        DwmGetWindowAttribute(
+            window,
+            DWMWA_CLOAKED,
+            &mut cloaked as *mut DWORD as _,
+            mem::size_of::<DWORD>() as DWORD,
+        )
+    };
+    if result == S_OK {
+        Ok(cloaked != 0)
+    } else {
+        Err(io::Error::from_raw_os_error(result))
+    }
+}
+
+// Whether the window would be listed by Alt+Tab, following Raymond Chen's
+// description of the switcher's rule ("Which windows appear in the Alt+Tab
+// list?", The Old New Thing, 2007-10-08), plus the checks Windows added
+// since:
+//
+// 1. Start from the window's root owner and follow `GetLastActivePopup`
+//    through invisible popups, stopping before a visible one. The window is
+//    listed only if the walk stops on it, so an owner with a visible dialog
+//    is listed and the dialog isn't.
+// 2. Tool windows are left out unless they are also marked `WS_EX_APPWINDOW`.
+// 3. Cloaked windows are left out, which covers other virtual desktops and
+//    suspended UWP apps.
+pub fn is_alt_tab_eligible(window: HWND) -> bool {
+    if !is_visible(window) {
+        return false;
+    }
+
+    let mut walk = unsafe { GetAncestor(window, GA_ROOTOWNER) };
+    if walk.is_null() {
+        walk = window;
+    }
+    for _ in 0..MAX_OWNER_CHAIN {
+        let next = unsafe { GetLastActivePopup(walk) };
+        if next == walk || is_visible(next) {
+            break;
+        }
+        walk = next;
+    }
+    if walk != window {
+        return false;
+    }
+
+    let ex_style = match get_window_ex_style(window) {
+        Ok(ex_style) => ex_style,
+        Err(_) => return false,
+    };
+    if ex_style & WS_EX_TOOLWINDOW != 0 && ex_style & WS_EX_APPWINDOW == 0 {
+        return false;
+    }
+
+    !is_cloaked(window).unwrap_or(false)
+}
+
+// The window's owner if it has one, otherwise its parent. `None` for an
+// unowned top-level window, whose parent is the desktop.
+pub fn get_owner_or_parent(window: HWND) -> Option<HWND> {
+    let owner = unsafe { GetWindow(window, GW_OWNER) };
+    if !owner.is_null() {
+        return Some(owner);
+    }
+
+    let parent = unsafe { GetAncestor(window, GA_PARENT) };
+    Some(parent).filter(|&parent| !parent.is_null() && parent != unsafe { GetDesktopWindow() })
+}
+
+// Deep enough for any real hierarchy; a longer chain is most likely a cycle
+// through windows that were destroyed and reused mid-walk.
+const MAX_OWNER_CHAIN: usize = 32;
+
+// The window followed by its owners or parents up to the root.
+pub fn owner_chain(window: HWND) -> Vec<HWND> {
+    let mut chain = Vec::new();
+    let mut seen = HashSet::new();
+    let mut next = Some(window);
+    while let Some(window) = next {
+        if chain.len() == MAX_OWNER_CHAIN || !seen.insert(handle::from_hwnd(window)) {
+            break;
+        }
+
+        chain.push(window);
+        next = get_owner_or_parent(window);
+    }
+
+    chain
+}
+
+// Heuristic for a modal dialog: an owned popup or modal-frame window whose
+// owner is disabled, which is what `DialogBox`, `MessageBox` and most
+// toolkits' modal loops do. Misses modals that leave their owner enabled and
+// block it some other way (most browser and Electron dialogs), modals owned
+// by a window of another process, and can be fooled by an app that disables
+// a window for its own reasons.
+pub fn is_modal(window: HWND) -> io::Result<bool> {
+    let owner = unsafe { GetWindow(window, GW_OWNER) };
+    if owner.is_null() {
+        return Ok(false);
+    }
+
+    let is_dialog_like = get_window_style(window)? & WS_POPUP != 0
+        || get_window_ex_style(window)? & WS_EX_DLGMODALFRAME != 0;
+    Ok(is_dialog_like && unsafe { IsWindowEnabled(owner) } == 0)
+}
+
+// Constant opacity of a layered window set with `SetLayeredWindowAttributes`,
+// from 0.0 to 1.0. `None` if the window has no alpha attribute, including
+// layered windows drawn with `UpdateLayeredWindow`, for which
+// `GetLayeredWindowAttributes` fails.
+pub fn get_layered_opacity(window: HWND) -> Option<f64> {
+    let mut alpha = 0;
+    let mut flags = 0;
+    let result = unsafe {
+        GetLayeredWindowAttributes(window, ptr::null_mut(), &mut alpha, &mut flags)
+    };
+    if result == 0 || flags & LWA_ALPHA == 0 {
+        return None;
+    }
+
+    Some(alpha as f64 / 255.0)
+}
+
+// Id of the process that created the window.
+pub fn get_window_process_id(window: HWND) -> io::Result<u32> {
+    let mut pid = 0;
+    if unsafe { GetWindowThreadProcessId(window, &mut pid) } != 0 {
+        Ok(pid)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// Id of the thread that created the window.
+pub fn get_window_thread_id(window: HWND) -> io::Result<u32> {
+    match unsafe { GetWindowThreadProcessId(window, ptr::null_mut()) } {
+        0 => Err(io::Error::last_os_error()),
+        thread_id => Ok(thread_id),
+    }
+}
+
+// The value stored under `key` with `SetPropW`, or `None` if the property
+// isn't set. Properties hold a handle-sized value, and a property that was
+// set to 0 is indistinguishable from one that isn't set.
+pub fn get_prop(window: HWND, key: &[u16]) -> Option<isize> {
+    debug_assert_eq!(key.last(), Some(&0));
+
+    let value = unsafe { GetPropW(window, key.as_ptr()) };
+    if value.is_null() {
+        None
+    } else {
+        Some(value as isize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    };
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use winapi::shared::{minwindef::UINT, winerror::ERROR_INVALID_WINDOW_HANDLE};
+    use winapi::um::{
+        libloaderapi::GetModuleHandleW,
+        processthreadsapi::GetCurrentThreadId,
+        winuser::{
+            CreateWindowExW, DestroyWindow, DispatchMessageW, GetMessageW, PostThreadMessageW,
+            SetWindowTextW, TranslateMessage, MSG, WM_APP, WM_QUIT, WS_OVERLAPPED,
+        },
+    };
+
+    // Posted to a `TestWindow`'s thread to stop it answering for `wParam`
+    // milliseconds.
+    const WM_BUSY: UINT = WM_APP;
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(Some(0)).collect()
+    }
+
+    // A window of this process on a thread of its own that pumps its
+    // messages, so reading it from a test thread goes through `WM_GETTEXT`
+    // as it does for a real window of ours.
+    struct TestWindow {
+        hwnd: isize,
+        thread_id: DWORD,
+        thread: Option<thread::JoinHandle<()>>,
+        // Set while the thread is busy.
+        busy: Arc<AtomicBool>,
+    }
+
+    impl TestWindow {
+        fn new(title: &str) -> Self {
+            let title = wide(title);
+            let (ready_tx, ready_rx) = mpsc::channel();
+            let busy = Arc::new(AtomicBool::new(false));
+            let thread_busy = busy.clone();
+            let thread = thread::spawn(move || {
+                let class_name = wide("STATIC");
+                let window = unsafe {
+                    CreateWindowExW(
+                        0,
+                        class_name.as_ptr(),
+                        title.as_ptr(),
+                        WS_OVERLAPPED,
+                        0,
+                        0,
+                        100,
+                        100,
+                        ptr::null_mut(),
+                        ptr::null_mut(),
+                        GetModuleHandleW(ptr::null()),
+                        ptr::null_mut(),
+                    )
+                };
+                let _ = ready_tx.send((handle::from_hwnd(window), unsafe { GetCurrentThreadId() }));
+                if window.is_null() {
+                    return;
+                }
+
+                let mut msg: MSG = unsafe { mem::zeroed() };
+                while unsafe { GetMessageW(&mut msg, ptr::null_mut(), 0, 0) } > 0 {
+                    if msg.hwnd.is_null() && msg.message == WM_BUSY {
+                        thread_busy.store(true, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(msg.wParam as u64));
+                        thread_busy.store(false, Ordering::SeqCst);
+                        continue;
+                    }
+                    unsafe {
+                        TranslateMessage(&msg);
+                        DispatchMessageW(&msg);
+                    }
+                }
+                unsafe { DestroyWindow(window) };
+            });
+
+            let (hwnd, thread_id) = ready_rx.recv().unwrap();
+            assert_ne!(hwnd, 0, "CreateWindowExW failed");
+            Self {
+                hwnd,
+                thread_id,
+                thread: Some(thread),
+                busy,
+            }
+        }
+
+        fn hwnd(&self) -> HWND {
+            handle::to_hwnd(self.hwnd)
+        }
+
+        fn set_title(&self, title: &str) {
+            let title = wide(title);
+            assert_ne!(unsafe { SetWindowTextW(self.hwnd(), title.as_ptr()) }, 0);
+        }
+
+        // Keep the window's thread from answering for `duration`, like an app
+        // whose main thread is running JavaScript. Returns once it's busy.
+        fn block(&self, duration: Duration) {
+            let ms = duration.as_millis() as usize;
+            assert_ne!(unsafe { PostThreadMessageW(self.thread_id, WM_BUSY, ms, 0) }, 0);
+            while !self.busy.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+        }
+
+        // Destroy the window, returning its now stale handle.
+        fn destroy(mut self) -> HWND {
+            self.close();
+            self.hwnd()
+        }
+
+        fn close(&mut self) {
+            if let Some(thread) = self.thread.take() {
+                unsafe { PostThreadMessageW(self.thread_id, WM_QUIT, 0, 0) };
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    impl Drop for TestWindow {
+        fn drop(&mut self) {
+            self.close();
+        }
+    }
+
+    fn assert_invalid_handle<T: std::fmt::Debug>(result: io::Result<T>) {
+        let err = result.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ERROR_INVALID_WINDOW_HANDLE as i32));
+    }
+
+    #[test]
+    fn reads_an_empty_title_as_none() {
+        let window = TestWindow::new("");
+        assert_eq!(get_window_text_length(window.hwnd()).unwrap(), None);
+        assert_eq!(get_window_text(window.hwnd()).unwrap(), None);
+
+        window.set_title("title");
+        assert_eq!(get_window_text_length(window.hwnd()).unwrap(), NonZeroUsize::new(5));
+        assert_eq!(get_window_text(window.hwnd()).unwrap().as_deref(), Some("title"));
+
+        window.set_title("");
+        assert_eq!(get_window_text(window.hwnd()).unwrap(), None);
+    }
+
+    #[test]
+    fn fails_for_a_destroyed_window() {
+        let window = TestWindow::new("title");
+        assert_eq!(get_class_name(window.hwnd()).unwrap(), "Static");
+
+        let stale = window.destroy();
+        assert_invalid_handle(get_window_text_length(stale));
+        assert_invalid_handle(get_window_text(stale));
+        assert_invalid_handle(get_class_name(stale));
+    }
+
+    #[test]
+    fn reads_a_title_that_changes_length_mid_read() {
+        const LONG: &str = "a title long enough to shrink from";
+        const SHORT: &str = "short";
+        let window = TestWindow::new(LONG);
+        let hwnd = window.hwnd;
+
+        // Swapping the title between the length query and the read makes it
+        // shrink, or grow and get truncated, under the reader.
+        let done = Arc::new(AtomicBool::new(false));
+        let toggler = {
+            let done = done.clone();
+            thread::spawn(move || {
+                let (long, short) = (wide(LONG), wide(SHORT));
+                while !done.load(Ordering::SeqCst) {
+                    for title in [&long, &short] {
+                        unsafe { SetWindowTextW(handle::to_hwnd(hwnd), title.as_ptr()) };
+                    }
+                }
+            })
+        };
+
+        let truncated = &LONG[..SHORT.len()];
+        for _ in 0..10_000 {
+            let title = get_window_text(window.hwnd()).unwrap().unwrap();
+            assert!(
+                title == LONG || title == SHORT || title == truncated,
+                "read {:?}",
+                title
+            );
+        }
+
+        done.store(true, Ordering::SeqCst);
+        toggler.join().unwrap();
+    }
+
+    #[test]
+    fn reads_own_windows_from_many_threads() {
+        let windows: Vec<_> = (0..4)
+            .map(|i| TestWindow::new(&format!("window {}", i)))
+            .collect();
+        let hwnds: Arc<Vec<isize>> = Arc::new(windows.iter().map(|window| window.hwnd).collect());
+
+        let readers: Vec<_> = (0..8)
+            .map(|reader| {
+                let hwnds = hwnds.clone();
+                thread::spawn(move || {
+                    for i in 0..200 {
+                        let n = (reader + i) % hwnds.len();
+                        let title = read_window_text(handle::to_hwnd(hwnds[n])).unwrap();
+                        assert_eq!(title, Some(format!("window {}", n)));
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_a_busy_window_without_waiting() {
+        let window = TestWindow::new("busy");
+        assert_eq!(read_window_text(window.hwnd()).unwrap().as_deref(), Some("busy"));
+
+        let busy = Duration::from_secs(1);
+        window.block(busy);
+        let started = Instant::now();
+        assert_eq!(read_window_text(window.hwnd()).unwrap().as_deref(), Some("busy"));
+        assert!(started.elapsed() < busy);
+    }
+
+    #[test]
+    fn reads_long_and_empty_own_titles() {
+        let window = TestWindow::new("");
+        assert_eq!(read_window_text(window.hwnd()).unwrap(), None);
+
+        for len in [255, 256, 257, 1000] {
+            let title = "x".repeat(len);
+            window.set_title(&title);
+            assert_eq!(read_window_text(window.hwnd()).unwrap(), Some(title));
+        }
+
+        assert_invalid_handle(read_window_text(window.destroy()));
+    }
+}
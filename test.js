@@ -2,6 +2,6 @@ const WindowForegroundListener = require("./index");
 
 const windowForegroundListener = new WindowForegroundListener();
 
-windowForegroundListener.start(0, (name) => {
-  console.log(name);
+windowForegroundListener.start(0, (event) => {
+  console.log(event);
 });
\ No newline at end of file
@@ -0,0 +1,77 @@
+"use strict";
+
+const test = require("node:test");
+const assert = require("node:assert/strict");
+
+const WindowForegroundListener = require("..");
+const { activeHookCount } = WindowForegroundListener;
+const { skip, churnWindow, waitFor, delay } = require("./helpers");
+
+const notFunctions = [undefined, null, 42, "callback", {}];
+
+// Every way to start, with `callback` in place of the callback.
+function starts(listener, hwnd, callback) {
+  return {
+    start: () => listener.start(0, callback),
+    startConfig: () => listener.startConfig({ pid: 0, callback }),
+    startMany: () => listener.startMany([{ pid: 0 }], callback),
+    trackWindow: () => listener.trackWindow(hwnd, callback),
+  };
+}
+
+test("a missing or non-function callback throws a TypeError naming it", { skip }, async (t) => {
+  const window = await churnWindow();
+  t.after(() => window.stop());
+  const listener = new WindowForegroundListener();
+
+  for (const callback of notFunctions) {
+    for (const [name, start] of Object.entries(starts(listener, window.hwnd, callback))) {
+      assert.throws(start, { name: "TypeError", message: /callback/ }, `${name} with ${callback}`);
+    }
+  }
+  assert.equal(activeHookCount(), 0);
+});
+
+test("a bad callback leaves a running listener running", { skip }, async (t) => {
+  const window = await churnWindow();
+  t.after(() => window.stop());
+  const listener = new WindowForegroundListener();
+  t.after(() => listener.stop());
+
+  let calls = 0;
+  let closeReason;
+  await listener.trackWindow(window.hwnd, () => calls++, {
+    onClose: (reason) => (closeReason = reason),
+  });
+  await waitFor(() => calls > 0, 5000, "a callback");
+  const hooks = activeHookCount();
+
+  for (const callback of notFunctions) {
+    for (const start of Object.values(starts(listener, window.hwnd, callback))) {
+      assert.throws(start, TypeError);
+    }
+  }
+
+  await delay(50);
+  assert.equal(closeReason, undefined);
+  assert.equal(activeHookCount(), hooks);
+  const before = calls;
+  await waitFor(() => calls > before, 5000, "callbacks to keep coming");
+});
+
+test("startPort needs a postMessage method but lets its getter's error through", { skip }, () => {
+  const listener = new WindowForegroundListener();
+
+  for (const port of [{}, { postMessage: 42 }]) {
+    assert.throws(() => listener.startPort(0, port), { name: "TypeError", message: /postMessage/ });
+  }
+
+  const error = new Error("getter failed");
+  const port = {
+    get postMessage() {
+      throw error;
+    },
+  };
+  assert.throws(() => listener.startPort(0, port), (thrown) => thrown === error);
+  assert.equal(activeHookCount(), 0);
+});
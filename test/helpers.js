@@ -0,0 +1,68 @@
+"use strict";
+
+const { spawn } = require("node:child_process");
+const { once } = require("node:events");
+const readline = require("node:readline");
+
+// The listeners need a Windows desktop. Elsewhere every test is skipped.
+const skip = process.platform !== "win32" && "needs a Windows desktop";
+
+// A real top-level window in another process whose title changes every
+// `intervalMs`, so a listener tracking it gets a steady stream of
+// "nameChange" events. Resolves with `{ hwnd, stop() }` once it is shown.
+async function churnWindow(intervalMs = 5) {
+  const script = `
+    Add-Type -AssemblyName System.Windows.Forms
+    $form = New-Object System.Windows.Forms.Form
+    $form.Text = 'win-foreground-listener test'
+    $form.ShowInTaskbar = $false
+    $timer = New-Object System.Windows.Forms.Timer
+    $timer.Interval = ${intervalMs}
+    $script:n = 0
+    $timer.add_Tick({ $script:n++; $form.Text = "win-foreground-listener test $script:n" })
+    $form.add_Shown({ [Console]::Out.WriteLine($form.Handle.ToInt64()); [Console]::Out.Flush(); $timer.Start() })
+    [System.Windows.Forms.Application]::Run($form)
+  `;
+  const child = spawn("powershell.exe", ["-NoProfile", "-NonInteractive", "-Command", script], {
+    stdio: ["ignore", "pipe", "inherit"],
+  });
+  const lines = readline.createInterface({ input: child.stdout });
+  const [hwnd] = await Promise.race([
+    once(lines, "line"),
+    once(child, "exit").then(([code]) => {
+      throw new Error(`window process exited with ${code} before showing its window`);
+    }),
+  ]);
+
+  return {
+    hwnd: hwnd.trim(),
+    stop() {
+      lines.close();
+      child.kill();
+    },
+  };
+}
+
+// Poll `predicate` until it returns true, failing after `timeoutMs`.
+async function waitFor(predicate, timeoutMs = 5000, what = "condition") {
+  const deadline = Date.now() + timeoutMs;
+  while (!predicate()) {
+    if (Date.now() >= deadline) {
+      throw new Error(`timed out waiting for ${what}`);
+    }
+    await delay(10);
+  }
+}
+
+function delay(ms) {
+  return new Promise((resolve) => setTimeout(resolve, ms));
+}
+
+// Keep the main thread busy without yielding to the event loop, like a slow
+// callback.
+function block(ms) {
+  const until = Date.now() + ms;
+  while (Date.now() < until) {}
+}
+
+module.exports = { skip, churnWindow, waitFor, delay, block };
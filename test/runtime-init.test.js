@@ -0,0 +1,35 @@
+"use strict";
+
+const test = require("node:test");
+const assert = require("node:assert/strict");
+
+const WindowForegroundListener = require("..");
+const { activeHookCount, shutdownRuntime } = WindowForegroundListener;
+const native = require("../index.node");
+const { skip, churnWindow, waitFor } = require("./helpers");
+
+const noHooks = !native.testFailNextRuntimeInit && "needs a build with the test-hooks feature";
+
+test("a runtime that fails to start throws, and the next start retries", { skip: skip || noHooks }, async (t) => {
+  const window = await churnWindow();
+  t.after(() => window.stop());
+  const listener = new WindowForegroundListener();
+  let calls = 0;
+  await listener.trackWindow(window.hwnd, () => calls++);
+  await waitFor(() => calls > 0, 5000, "a callback");
+
+  // The next start has to create the runtime again.
+  await shutdownRuntime();
+  native.testFailNextRuntimeInit();
+  assert.throws(() => listener.trackWindow(window.hwnd, () => {}), {
+    code: "ERR_RUNTIME_INIT",
+    message: /Failed to create the tokio runtime/,
+  });
+  assert.equal(activeHookCount(), 0);
+
+  // Nothing was left half-initialized.
+  calls = 0;
+  await listener.trackWindow(window.hwnd, () => calls++);
+  await waitFor(() => calls > 0, 5000, "a callback after the retry");
+  listener.stop();
+});
@@ -0,0 +1,69 @@
+"use strict";
+
+const test = require("node:test");
+const assert = require("node:assert/strict");
+
+const WindowForegroundListener = require("..");
+const { activeHookCount, shutdownRuntime } = WindowForegroundListener;
+const native = require("../index.node");
+const { skip, churnWindow, waitFor } = require("./helpers");
+
+const noHooks = !native.testTaskRuntimes && "needs a build with the test-hooks feature";
+
+// Track `hwnd` until the first callback, resolving with a promise of the
+// close reason.
+async function trackUntilCalled(listener, hwnd) {
+  let calls = 0;
+  let onClose;
+  const closed = new Promise((resolve) => (onClose = resolve));
+  await listener.trackWindow(hwnd, () => calls++, { onClose });
+  await waitFor(() => calls > 0, 5000, "a callback");
+  return closed;
+}
+
+test("start, shutdownRuntime, start again", { skip }, async (t) => {
+  const window = await churnWindow();
+  t.after(() => window.stop());
+  const listener = new WindowForegroundListener();
+
+  for (let cycle = 0; cycle < 3; cycle++) {
+    const closed = await trackUntilCalled(listener, window.hwnd);
+    assert.ok(activeHookCount() > 0);
+
+    // Every listener is unhooked before the runtime goes away.
+    assert.equal(await shutdownRuntime(), true);
+    assert.equal(await closed, "stopped");
+    assert.equal(activeHookCount(), 0);
+  }
+});
+
+test("every listener runs on the one global runtime", { skip: skip || noHooks }, async (t) => {
+  const window = await churnWindow();
+  t.after(() => window.stop());
+
+  // Distinct instances, and several tasks in one instance.
+  const listeners = [1, 2, 3].map(() => new WindowForegroundListener());
+  for (const listener of listeners) {
+    await trackUntilCalled(listener, window.hwnd);
+  }
+  const many = new WindowForegroundListener();
+  await many.startMany([{ pid: 0 }, { pid: 0 }], () => {});
+
+  const runtimes = native.testTaskRuntimes();
+  assert.equal(runtimes.length, 5);
+  assert.notEqual(runtimes[0], 0, "a task isn't on a global runtime thread");
+  assert.deepEqual(new Set(runtimes), new Set([runtimes[0]]));
+
+  // After a shutdown, new listeners all share the new runtime.
+  assert.equal(await shutdownRuntime(), true);
+  assert.deepEqual(native.testTaskRuntimes(), []);
+  for (const listener of listeners.slice(0, 2)) {
+    await trackUntilCalled(listener, window.hwnd);
+  }
+  const restarted = native.testTaskRuntimes();
+  assert.equal(restarted.length, 2);
+  assert.equal(restarted[0], restarted[1]);
+  assert.notEqual(restarted[0], runtimes[0]);
+
+  assert.equal(await shutdownRuntime(), true);
+});
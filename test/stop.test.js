@@ -0,0 +1,61 @@
+"use strict";
+
+const test = require("node:test");
+const assert = require("node:assert/strict");
+
+const WindowForegroundListener = require("..");
+const { activeHookCount } = WindowForegroundListener;
+const { skip, churnWindow, waitFor, delay, block } = require("./helpers");
+
+test("stop() while callbacks are firing unhooks and calls back no more", { skip }, async (t) => {
+  const window = await churnWindow();
+  t.after(() => window.stop());
+
+  for (let i = 0; i < 25; i++) {
+    const listener = new WindowForegroundListener();
+    let calls = 0;
+    let callsAfterStop = 0;
+    let stopped = false;
+    let onClose;
+    const closed = new Promise((resolve) => (onClose = resolve));
+
+    await listener.trackWindow(
+      window.hwnd,
+      () => {
+        calls++;
+        if (stopped) {
+          callsAfterStop++;
+        }
+        // Slow enough that events queue up behind the callback.
+        block(2);
+      },
+      { onClose }
+    );
+    // Stop after a varying number of callbacks, with more queued behind.
+    await waitFor(() => calls > i % 5, 5000, "callbacks");
+    listener.stop();
+    stopped = true;
+
+    assert.equal(await closed, "stopped");
+    await delay(50);
+    assert.equal(callsAfterStop, 0);
+  }
+
+  await waitFor(() => activeHookCount() === 0, 5000, "every hook to be removed");
+});
+
+test("stop() right after start() leaves no hooks behind", { skip }, async () => {
+  const listeners = [];
+  for (let i = 0; i < 50; i++) {
+    const listener = new WindowForegroundListener();
+    listener.start(0, () => {});
+    if (i % 2) {
+      // Restarting replaces the running listener.
+      listener.start(0, () => {});
+    }
+    listener.stop();
+    listeners.push(listener);
+  }
+
+  await waitFor(() => activeHookCount() === 0, 5000, "every hook to be removed");
+});
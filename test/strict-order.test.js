@@ -0,0 +1,46 @@
+"use strict";
+
+const test = require("node:test");
+const assert = require("node:assert/strict");
+
+const WindowForegroundListener = require("..");
+const { droppedEventCount } = WindowForegroundListener;
+const { skip, churnWindow, waitFor, block } = require("./helpers");
+
+test("strictOrder numbers events in order and counts the ones it drops", { skip }, async (t) => {
+  const window = await churnWindow(1);
+  t.after(() => window.stop());
+  const droppedAtStart = droppedEventCount();
+
+  const listener = new WindowForegroundListener();
+  t.after(() => listener.stop());
+  const events = [];
+  await listener.trackWindow(
+    window.hwnd,
+    (event) => {
+      events.push({ seq: event.seq, droppedBefore: event.droppedBefore });
+      // Fall behind now and then, so the buffer overflows.
+      if (events.length % 50 === 0) {
+        block(300);
+      }
+    },
+    { strictOrder: true, strictOrderBufferSize: 4 }
+  );
+  await waitFor(() => events.length >= 200, 30000, "200 events");
+  listener.stop();
+
+  let dropped = events[0].droppedBefore;
+  for (let i = 1; i < events.length; i++) {
+    const [previous, event] = [events[i - 1], events[i]];
+    assert.ok(event.seq > previous.seq, `seq ${event.seq} after ${previous.seq}`);
+    // Filtered events skip a `seq` too, but only dropped ones are counted.
+    assert.ok(
+      event.seq - previous.seq - 1 >= event.droppedBefore,
+      `${event.droppedBefore} dropped between seq ${previous.seq} and ${event.seq}`
+    );
+    dropped += event.droppedBefore;
+  }
+  assert.ok(dropped > 0, "the buffer never overflowed");
+  // Drops after the last delivered event are only in the global count.
+  assert.ok(droppedEventCount() - droppedAtStart >= dropped);
+});